@@ -0,0 +1,346 @@
+use crate::player::{JUMP_SPEED, JUMP_WINDUP};
+use crate::prelude::*;
+use crate::stage::{note_at, StageDescription, BALL_DROP_TIME, PICKUP_TIME};
+use std::collections::VecDeque;
+use std::fmt;
+
+const UNREACHABLE: u32 = std::u32::MAX;
+
+/// How hard a chart is to play, worked out from its drops rather than its tempo.
+#[derive(Debug, Clone)]
+pub struct Analysis {
+    pub name: String,
+    /// Sub-beats analysed: the whole song, or one cycle of a looping song.
+    pub length: i32,
+    pub duration: f32,
+    pub drops: usize,
+    pub drops_per_second: f32,
+    pub peak_drops: usize,
+    pub peak_beat: i32,
+    /// Platforms no ball lands on, for each sub-beat.
+    pub safe_platforms: Vec<usize>,
+    /// Fewest jumps that survive every drop, or None if some drop can't be dodged.
+    pub min_jumps: Option<u32>,
+    pub jumps_per_second: f32,
+    /// Notes paid out, and how many of them a player who survives can get to.
+    pub rewards: usize,
+    pub reachable_rewards: usize,
+    pub rating: f32,
+}
+
+impl Analysis {
+    pub fn min_safe_platforms(&self) -> usize {
+        self.safe_platforms.iter().cloned().min().unwrap_or(0)
+    }
+
+    pub fn grade(&self) -> &'static str {
+        match self.rating {
+            _ if self.min_jumps.is_none() => "impossible",
+            rating if rating < 2.0 => "easy",
+            rating if rating < 4.0 => "normal",
+            rating if rating < 6.0 => "hard",
+            _ => "expert",
+        }
+    }
+}
+
+impl fmt::Display for Analysis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{}: {:.1} ({}), {} sub-beats in {:.1}s",
+            self.name,
+            self.rating,
+            self.grade(),
+            self.length,
+            self.duration
+        )?;
+        writeln!(
+            f,
+            "  {} drops, {:.2} per second, at most {} at once (beat {})",
+            self.drops, self.drops_per_second, self.peak_drops, self.peak_beat
+        )?;
+        writeln!(
+            f,
+            "  at least {} platforms safe on every sub-beat",
+            self.min_safe_platforms()
+        )?;
+        match self.min_jumps {
+            Some(jumps) => writeln!(
+                f,
+                "  {} jumps needed to survive, {:.2} per second",
+                jumps, self.jumps_per_second
+            )?,
+            None => writeln!(f, "  cannot be survived")?,
+        }
+        write!(
+            f,
+            "  {} of {} rewards reachable",
+            self.reachable_rewards, self.rewards
+        )
+    }
+}
+
+// Platform notes, in the order analysis vectors are indexed.
+fn platform_notes(stage: &StageDescription) -> Vec<Note> {
+    let (width, height) = stage.size();
+    (0..height)
+        .flat_map(|y| (0..width).map(move |x| note_at(x, y)))
+        .collect()
+}
+
+// Jumps between every pair of platforms.
+fn jump_distances(stage: &StageDescription, notes: &[Note]) -> Vec<Vec<u32>> {
+    notes
+        .iter()
+        .map(|start| {
+            let mut distances = vec![UNREACHABLE; notes.len()];
+            let mut queue = VecDeque::new();
+            if let Some(index) = notes.iter().position(|note| note == start) {
+                distances[index] = 0;
+                queue.push_back(index);
+            }
+            while let Some(index) = queue.pop_front() {
+                for neighbour in stage.neighbours(notes[index]) {
+                    if let Some(next) = notes.iter().position(|note| *note == neighbour) {
+                        if distances[next] == UNREACHABLE {
+                            distances[next] = distances[index] + 1;
+                            queue.push_back(next);
+                        }
+                    }
+                }
+            }
+            distances
+        })
+        .collect()
+}
+
+/// Analyses a chart played on `stage` by a player jumping at `jump_speed` who
+/// sees each ball `lead_time` seconds before it lands.
+pub fn analyze_song(
+    song: &Song,
+    stage: &StageDescription,
+    jump_speed: f32,
+    lead_time: f32,
+) -> Analysis {
+    let notes = platform_notes(stage);
+    let distances = jump_distances(stage, &notes);
+    let length = song.length().unwrap_or_else(|| song.cycle_length()).max(0);
+    let duration = song.time_at(length as f32).max(0.001);
+    let jump_time = JUMP_WINDUP + 1.0 / jump_speed.max(0.001);
+
+    let mut drops = 0;
+    let mut peak = (0, 0);
+    let mut safe_platforms = Vec::new();
+    // Sub-beats with drops, with the time their balls land and the platforms hit.
+    let mut impacts: Vec<(f32, Vec<bool>)> = Vec::new();
+    for beat in 0..length {
+        let dropped = song.get_notes_at(beat);
+        let hit = notes
+            .iter()
+            .map(|note| dropped.contains(note))
+            .collect::<Vec<bool>>();
+        drops += dropped.len();
+        if dropped.len() > peak.0 {
+            peak = (dropped.len(), beat);
+        }
+        safe_platforms.push(hit.iter().filter(|hit| !**hit).count());
+        if !dropped.is_empty() {
+            impacts.push((song.time_of_beat(beat) + lead_time, hit));
+        }
+    }
+    // Whether a trip of `distance` jumps can leave a platform after impact `from`
+    // lands, while the player still stands on it, and land after the impact
+    // before `to` but in time for `to`. Balls landing while the player is in the
+    // air can't hit them, so the trip may span other impacts.
+    let trip_time = |distance: u32| distance as f32 * jump_time;
+    let can_travel = |from: usize, to: usize, distance: u32| {
+        let leave_by = impacts[from + 1].0;
+        impacts[from].0 + trip_time(distance) <= impacts[to].0
+            && leave_by + trip_time(distance) > impacts[to - 1].0
+    };
+    let longest_trip = trip_time(
+        distances
+            .iter()
+            .flatten()
+            .cloned()
+            .filter(|distance| *distance != UNREACHABLE)
+            .max()
+            .unwrap_or(0),
+    );
+
+    // Fewest jumps to be standing on each platform, unhit, as each impact lands.
+    // The count-in leaves time to reach any platform before the first one.
+    let mut jumps: Vec<Vec<u32>> = Vec::new();
+    for (index, (_, hit)) in impacts.iter().enumerate() {
+        let mut row = hit
+            .iter()
+            .map(|hit| if *hit { UNREACHABLE } else { 0 })
+            .collect::<Vec<u32>>();
+        if index > 0 {
+            for to in 0..notes.len() {
+                if !hit[to] {
+                    row[to] = jumps[index - 1][to];
+                }
+            }
+            let departures = (0..index)
+                .rev()
+                .take_while(|from| impacts[from + 1].0 + longest_trip > impacts[index - 1].0);
+            for from in departures {
+                for (start, start_jumps) in jumps[from].iter().enumerate() {
+                    if *start_jumps == UNREACHABLE {
+                        continue;
+                    }
+                    for to in 0..notes.len() {
+                        let distance = distances[start][to];
+                        if !hit[to]
+                            && distance != 0
+                            && distance != UNREACHABLE
+                            && can_travel(from, index, distance)
+                        {
+                            row[to] = row[to].min(start_jumps + distance);
+                        }
+                    }
+                }
+            }
+        }
+        jumps.push(row);
+    }
+    // Whether the player can still survive the rest of the chart from each
+    // platform at each impact.
+    let mut survivable = vec![vec![false; notes.len()]; impacts.len()];
+    for index in (0..impacts.len()).rev() {
+        for from in 0..notes.len() {
+            survivable[index][from] = !impacts[index].1[from]
+                && (index + 1 == impacts.len()
+                    || survivable[index + 1][from]
+                    || (index + 1..impacts.len())
+                        .take_while(|to| impacts[index + 1].0 + longest_trip > impacts[to - 1].0)
+                        .any(|to| {
+                            (0..notes.len()).any(|landing| {
+                                let distance = distances[from][landing];
+                                survivable[to][landing]
+                                    && distance != 0
+                                    && distance != UNREACHABLE
+                                    && can_travel(index, to, distance)
+                            })
+                        }));
+        }
+    }
+    let min_jumps = match jumps.last() {
+        None => Some(0),
+        Some(last) => last
+            .iter()
+            .cloned()
+            .filter(|jumps| *jumps != UNREACHABLE)
+            .min(),
+    };
+
+    // A reward is reachable if its platform can be stood on, on a path that
+    // survives, while it is out.
+    let mut rewards = 0;
+    let mut reachable_rewards = 0;
+    for beat in 0..length {
+        for reward in song.get_rewards_at(beat, &Vec::new()) {
+            rewards += 1;
+            let platform = match notes.iter().position(|note| *note == reward) {
+                Some(platform) => platform,
+                None => continue,
+            };
+            let start = song.time_of_beat(beat);
+            let end = start + PICKUP_TIME;
+            let mut quiet = true;
+            let mut reachable = false;
+            for (index, (time, _)) in impacts.iter().enumerate() {
+                if *time >= start && *time <= end {
+                    quiet = false;
+                    reachable |=
+                        jumps[index][platform] != UNREACHABLE && survivable[index][platform];
+                }
+            }
+            if reachable || (quiet && min_jumps.is_some()) {
+                reachable_rewards += 1;
+            }
+        }
+    }
+
+    let drops_per_second = drops as f32 / duration;
+    let jumps_per_second = min_jumps.unwrap_or(0) as f32 / duration;
+    let min_safe = safe_platforms.iter().cloned().min().unwrap_or(notes.len());
+    let crowding = 1.0 - min_safe as f32 / notes.len().max(1) as f32;
+    let reach = if rewards > 0 {
+        reachable_rewards as f32 / rewards as f32
+    } else {
+        1.0
+    };
+    // Less warning than a jump takes means moving before the ball is seen.
+    let pressure = (jump_time / lead_time.max(0.001)).min(2.0);
+    let rating = drops_per_second * 0.5
+        + jumps_per_second * 3.0
+        + crowding * 3.0
+        + (1.0 - reach) * 2.0
+        + pressure;
+
+    Analysis {
+        name: song.name.clone(),
+        length,
+        duration,
+        drops,
+        drops_per_second,
+        peak_drops: peak.0,
+        peak_beat: peak.1,
+        safe_platforms,
+        min_jumps,
+        jumps_per_second,
+        rewards,
+        reachable_rewards,
+        rating,
+    }
+}
+
+/// Analyses a chart as the game plays it.
+pub fn analyze(song: &Song, stage: &StageDescription) -> Analysis {
+    analyze_song(song, stage, JUMP_SPEED, BALL_DROP_TIME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::music::Substructure;
+    use std::collections::HashMap;
+
+    // A ball on the first platform, landing `lead_time` after the start, and a
+    // reward on the same platform two seconds in.
+    fn drop_then_reward() -> Song {
+        let mut rewards = HashMap::new();
+        rewards.insert(8, 0);
+        Song {
+            name: "Drop then reward".to_string(),
+            bpm: 60,
+            structures: vec![Substructure::Scale {
+                notes: vec![0],
+                interval: 16,
+            }],
+            payouts: vec![Substructure::Round {
+                notes: rewards,
+                rounds: 1,
+                repeat_at: 0,
+                restart_at: 16,
+                pitch_up: 0,
+                loops: Some(1),
+            }],
+            ..Song::default()
+        }
+    }
+
+    #[test]
+    fn balls_land_after_the_lead_time() {
+        let song = drop_then_reward();
+        let stage = StageDescription::default();
+        let early = analyze_song(&song, &stage, JUMP_SPEED, 1.0);
+        assert_eq!((early.rewards, early.reachable_rewards), (1, 1));
+        // The ball now lands on the reward while it is out.
+        let late = analyze_song(&song, &stage, JUMP_SPEED, 3.0);
+        assert_eq!((late.rewards, late.reachable_rewards), (1, 0));
+    }
+}
@@ -1,12 +1,15 @@
+use crate::music::Song;
 use crate::stage::{MissIndicator, NoteIndicator};
+use crate::synth::{synthesize_wav, SynthPatch};
 use amethyst::{
     animation::*,
     assets::*,
-    audio::{SourceHandle, WavFormat},
+    audio::{AudioData, SourceHandle, WavFormat},
     core::Transform,
     derive::PrefabData,
     ecs::*,
     error::Error,
+    log::warn,
     renderer::{
         sprite::{prefab::SpriteScenePrefab, SpriteSheetHandle},
         types::Texture,
@@ -16,6 +19,7 @@ use amethyst::{
 };
 use na::Vector2;
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 
 #[derive(Debug, Clone, Deserialize, PrefabData)]
 pub struct SpriteEntityPrefabData {
@@ -55,6 +59,101 @@ pub fn load_sound_file<'a>(
     loader.load(path, WavFormat, (), &world.read_resource())
 }
 
+/// Where an instrument's notes come from: a directory of per-note WAV files under
+/// assets, falling back to the synth for missing files, or the synth alone.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum InstrumentSource {
+    Samples {
+        directory: String,
+        fallback: SynthPatch,
+    },
+    Synth(SynthPatch),
+}
+
+impl InstrumentSource {
+    /// Path under assets of the sample for `note`, if the instrument has one on disk.
+    pub fn sample_path(&self, note: &str) -> Option<String> {
+        match self {
+            InstrumentSource::Samples { directory, .. } => {
+                let path = format!("{}/{}.wav", directory, note);
+                let root = application_root_dir().ok()?;
+                if root.join("assets").join(&path).is_file() {
+                    Some(path)
+                } else {
+                    None
+                }
+            }
+            InstrumentSource::Synth(_) => None,
+        }
+    }
+
+    pub fn patch(&self) -> &SynthPatch {
+        match self {
+            InstrumentSource::Samples { fallback, .. } => fallback,
+            InstrumentSource::Synth(patch) => patch,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Instruments {
+    pub foo: InstrumentSource,
+    pub note: InstrumentSource,
+}
+
+impl Default for Instruments {
+    fn default() -> Self {
+        Instruments {
+            foo: InstrumentSource::Samples {
+                directory: "foo".to_string(),
+                fallback: SynthPatch::pluck(),
+            },
+            note: InstrumentSource::Samples {
+                directory: "note".to_string(),
+                fallback: SynthPatch::bell(),
+            },
+        }
+    }
+}
+
+/// Reads assets/instruments.ron, using sampled instruments if it is missing or invalid.
+pub fn load_instruments() -> Instruments {
+    let path = match application_root_dir() {
+        Ok(root) => root.join("assets").join("instruments.ron"),
+        Err(_) => return Instruments::default(),
+    };
+    match std::fs::read_to_string(&path) {
+        Ok(text) => ron::de::from_str(&text).unwrap_or_else(|err| {
+            warn!("Invalid {}: {}", path.display(), err);
+            Instruments::default()
+        }),
+        Err(_) => Instruments::default(),
+    }
+}
+
+/// One sound per note in SCALE.
+pub fn load_instrument_scale<'a>(
+    world: &mut World,
+    instrument: &InstrumentSource,
+    progress: &'a mut ProgressCounter,
+) -> Vec<SourceHandle> {
+    SCALE
+        .iter()
+        .enumerate()
+        .map(|(note, name)| match instrument.sample_path(name) {
+            Some(path) => load_sound_file(world, path, progress),
+            None => {
+                let loader = world.read_resource::<Loader>();
+                loader.load_from_data(
+                    AudioData(synthesize_wav(instrument.patch(), note)),
+                    &mut *progress,
+                    &world.read_resource(),
+                )
+            }
+        })
+        .collect()
+}
+
 pub fn load_texture<'a>(
     world: &mut World,
     path: String,
@@ -80,6 +179,46 @@ pub fn load_spritesheet<'a>(
     )
 }
 
+pub fn load_song<'a>(
+    world: &mut World,
+    path: String,
+    progress: &'a mut ProgressCounter,
+) -> Handle<Song> {
+    let loader = world.read_resource::<Loader>();
+    loader.load(path, RonFormat, progress, &world.read_resource())
+}
+
+/// Song files in assets/songs, in the order they are played.
+pub fn song_paths() -> Vec<PathBuf> {
+    let mut paths = application_root_dir()
+        .ok()
+        .and_then(|root| std::fs::read_dir(root.join("assets").join("songs")).ok())
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().map_or(false, |ext| ext == "ron"))
+                .collect::<Vec<PathBuf>>()
+        })
+        .unwrap_or_default();
+    paths.sort();
+    paths
+}
+
+pub fn load_song_library<'a>(world: &mut World, progress: &'a mut ProgressCounter) -> SongStorage {
+    let songs = song_paths()
+        .iter()
+        .filter_map(|path| path.file_name())
+        .map(|name| load_song(world, format!("songs/{}", name.to_string_lossy()), progress))
+        .collect();
+    SongStorage { songs }
+}
+
+#[derive(Clone)]
+pub struct SongStorage {
+    pub songs: Vec<Handle<Song>>,
+}
+
 #[derive(Clone)]
 pub struct SpriteStorage {
     pub master: SpriteSheetHandle,
@@ -98,6 +237,7 @@ pub struct SoundStorage {
     pub tap: SourceHandle,
     pub foo_scale: Vec<SourceHandle>,
     pub note_scale: Vec<SourceHandle>,
+    pub instruments: Instruments,
 }
 
 #[derive(Eq, PartialOrd, PartialEq, Hash, Debug, Copy, Clone, Deserialize, Serialize)]
@@ -113,4 +253,4 @@ pub enum AnimationId {
     //    Kill,
 }
 
-pub type GameAssets = (SpriteStorage, PrefabStorage, SoundStorage);
+pub type GameAssets = (SpriteStorage, PrefabStorage, SoundStorage, SongStorage);
@@ -0,0 +1,140 @@
+use crate::player::{PlayerState, JUMP_WINDUP};
+use crate::prelude::*;
+use crate::replay::PlayerInput;
+use crate::stage::PICKUP_TIME;
+
+// How far ahead the bot reads the song for drops.
+const LOOKAHEAD: f32 = 2.0;
+// Time spent walking to the platform edge before a jump starts.
+const DEPART_TIME: f32 = JUMP_WINDUP + 0.05;
+// Slack kept around every impact.
+const MARGIN: f32 = 0.1;
+// A platform safe for this long is worth jumping to for a note.
+const SAFE_TIME: f32 = 0.5;
+const REWARD_BONUS: f32 = 2.0;
+const DIRECTIONS: [(f32, f32); 4] = [(1.0, 0.0), (-1.0, 0.0), (0.0, 1.0), (0.0, -1.0)];
+
+/// Whether the bot plays instead of the keyboard.
+#[derive(Debug, Clone, Default)]
+pub struct Autoplay {
+    pub enabled: bool,
+}
+
+// Impacts from now until the lookahead, as (note, seconds from now), and the
+// notes still waiting to be picked up.
+fn read_song(stage: &StageState) -> (Vec<(Note, f32)>, Vec<Note>) {
+    let song = stage.song();
+    let now = stage.drop_time();
+    let lead_time = stage.lead_time();
+    let mut impacts = Vec::new();
+    for beat in song.beat_at(now - lead_time).max(0)..=song.beat_at(now + LOOKAHEAD) {
+        let impact = song.time_of_beat(beat) + lead_time - now;
+        if impact >= 0.0 {
+            for note in song.get_notes_at(beat) {
+                impacts.push((note, impact));
+            }
+        }
+    }
+    let mut rewards = Vec::new();
+    if !stage.winning {
+        for beat in song.beat_at(now - PICKUP_TIME).max(0)..=song.beat_at(now) {
+            rewards.append(&mut song.get_rewards_at(beat, &stage.notes_found));
+        }
+    }
+    (impacts, rewards)
+}
+
+// Seconds from now until the first impact on a note at or after `after`.
+fn next_impact(impacts: &[(Note, f32)], note: Note, after: f32) -> f32 {
+    impacts
+        .iter()
+        .filter(|(impact_note, time)| *impact_note == note && *time >= after)
+        .map(|(_, time)| *time)
+        .fold(std::f32::INFINITY, f32::min)
+}
+
+/// Plays the game through `PlayerInput`, as a player holding the keys would:
+/// it steps off platforms a ball is about to land on and goes for notes when
+/// a neighbouring platform is safe.
+#[derive(Default)]
+pub struct AutoplaySystem {
+    // Direction held until the jump it started takes off.
+    heading: Option<(f32, f32)>,
+}
+
+impl AutoplaySystem {
+    fn plan(
+        &self,
+        stage: &StageState,
+        platforms: &ReadStorage<Platform>,
+        current: Platform,
+        jump_time: f32,
+    ) -> Option<(f32, f32)> {
+        let (impacts, rewards) = read_song(stage);
+        let stay = next_impact(&impacts, current.note, 0.0);
+        let arrival = DEPART_TIME + jump_time;
+        // (direction, value, seconds safe after landing, note waiting there)
+        let mut best: Option<((f32, f32), f32, f32, bool)> = None;
+        for &(tx, ty) in DIRECTIONS.iter() {
+            let target = match stage
+                .target_platform(current, tx, ty)
+                .and_then(|entity| platforms.get(*entity))
+            {
+                Some(target) if !target.dead => *target,
+                _ => continue,
+            };
+            // Balls landing while the player is in the air can't hit them.
+            let safe = next_impact(&impacts, target.note, arrival - MARGIN) - arrival;
+            let reward = rewards.contains(&target.note);
+            let value = safe.min(LOOKAHEAD) + if reward { REWARD_BONUS } else { 0.0 };
+            if best.map_or(true, |(_, best_value, _, _)| value > best_value) {
+                best = Some(((tx, ty), value, safe, reward));
+            }
+        }
+        let (heading, _, safe, reward) = best?;
+        let must_move = stay <= DEPART_TIME + MARGIN;
+        if (must_move && safe > stay.min(LOOKAHEAD)) || (reward && safe >= SAFE_TIME) {
+            Some(heading)
+        } else {
+            None
+        }
+    }
+}
+
+impl<'s> System<'s> for AutoplaySystem {
+    type SystemData = (
+        Read<'s, Autoplay>,
+        Read<'s, StageState>,
+        ReadStorage<'s, Player>,
+        ReadStorage<'s, Platform>,
+        Write<'s, PlayerInput>,
+    );
+
+    fn run(&mut self, (autoplay, stage, players, platforms, mut input): Self::SystemData) {
+        if !autoplay.enabled {
+            return;
+        }
+        let mut tilt = (0.0, 0.0);
+        for player in (&players).join() {
+            match player.state {
+                PlayerState::Waiting { .. } => {
+                    self.heading = None;
+                    tilt = (0.0, 1.0);
+                }
+                PlayerState::Idle | PlayerState::Moving { .. } => {
+                    let current = player.platform.and_then(|platform| platforms.get(platform));
+                    if self.heading.is_none() {
+                        if let Some(current) = current {
+                            self.heading =
+                                self.plan(&stage, &platforms, *current, 1.0 / player.jump_speed);
+                        }
+                    }
+                    tilt = self.heading.unwrap_or((0.0, 0.0));
+                }
+                _ => self.heading = None,
+            }
+        }
+        input.set_axis("leftright", tilt.0);
+        input.set_axis("updown", tilt.1);
+    }
+}
@@ -0,0 +1,312 @@
+use crate::analysis::analyze;
+use crate::assets::{load_instruments, song_paths};
+use crate::generator::*;
+use crate::midi::*;
+use crate::music::SUBNOTES;
+use crate::prelude::*;
+use crate::render::*;
+use crate::sim::Simulation;
+use crate::stage::StageDescription;
+use crate::validate::*;
+use amethyst::utils::application_root_dir;
+use std::fs;
+use std::path::Path;
+
+fn read_song(path: &str) -> Result<Song, String> {
+    let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+    ron::de::from_str(&text).map_err(|err| format!("{}: {}", path, err))
+}
+
+// Reads a song, refusing it only if it couldn't be played at all. Charts the
+// game would still refuse, like fresh imports without payouts, are allowed.
+fn read_playable_song(path: &str) -> Result<Song, String> {
+    let song = read_song(path)?;
+    let diagnostics = validate_timing(&song);
+    log_diagnostics(&diagnostics);
+    if has_errors(&diagnostics) {
+        return Err(format!(
+            "{}: song can't be played, run check-songs for its errors",
+            path
+        ));
+    }
+    Ok(song)
+}
+
+fn write_song(path: &str, song: &Song) -> Result<(), String> {
+    let text = ron::ser::to_string_pretty(song, ron::ser::PrettyConfig::default())
+        .map_err(|err| format!("{}: {}", path, err))?;
+    fs::write(path, text).map_err(|err| format!("{}: {}", path, err))
+}
+
+fn import_midi_tool(input: &str, output: &str, subdivision: Option<&str>) -> Result<(), String> {
+    let subdivision = match subdivision {
+        Some(subdivision) => subdivision
+            .parse::<i32>()
+            .map_err(|_| format!("Invalid subdivision: {}", subdivision))?,
+        None => SUBNOTES,
+    };
+    let bytes = fs::read(input).map_err(|err| format!("{}: {}", input, err))?;
+    let mut import = import_midi(&bytes, subdivision)?;
+    if import.song.name.is_empty() {
+        import.song.name = Path::new(input)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+    }
+    for issue in import.issues.iter() {
+        println!("{}", issue);
+    }
+    println!(
+        "Imported {} at {} bpm with {} structures ({} notes folded or dropped)",
+        import.song.name,
+        import.song.bpm,
+        import.song.structures.len(),
+        import.issues.len()
+    );
+    write_song(output, &import.song)
+}
+
+// Songs are played in order. "payout" plays the payout for the previous song's
+// next notes, and "lose" plays a shuffled lose song.
+fn export_midi_tool(output: &str, inputs: &[String]) -> Result<(), String> {
+    let mut songs: Vec<Song> = Vec::new();
+    for input in inputs.iter() {
+        let song = match input.as_str() {
+            "payout" => Song::payout_song(
+                &songs
+                    .last()
+                    .map(|song| song.next_notes.clone())
+                    .unwrap_or_default(),
+            ),
+            "lose" => Song::lose_song(),
+            path => read_playable_song(path)?,
+        };
+        songs.push(song);
+    }
+    if songs.is_empty() {
+        return usage();
+    }
+    export_session(&songs)?
+        .save(output)
+        .map_err(|err| format!("{}: {}", output, err))
+}
+
+// The songs named on the command line, or every song in the library.
+fn song_args(inputs: &[String]) -> Vec<String> {
+    if inputs.is_empty() {
+        song_paths()
+            .iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect()
+    } else {
+        inputs.to_vec()
+    }
+}
+
+fn check_songs_tool(inputs: &[String]) -> Result<(), String> {
+    let paths = song_args(inputs);
+    let stage = StageDescription::default();
+    let mut failed = 0;
+    for path in paths.iter() {
+        let diagnostics = match read_song(path) {
+            Ok(song) => validate_song(&song, &stage),
+            Err(err) => {
+                println!("error: {}", err);
+                failed += 1;
+                continue;
+            }
+        };
+        for diagnostic in diagnostics.iter() {
+            println!("{}", diagnostic);
+        }
+        if has_errors(&diagnostics) {
+            failed += 1;
+        }
+    }
+    println!("Checked {} songs, {} with errors", paths.len(), failed);
+    if failed > 0 {
+        Err(format!("{} songs failed validation", failed))
+    } else {
+        Ok(())
+    }
+}
+
+fn generate_tool(code: &str, output: &str) -> Result<(), String> {
+    let settings = GeneratorSettings::from_code(code).ok_or_else(|| {
+        format!(
+            "Invalid song code {}, expected seed-key-bpm-difficulty",
+            code
+        )
+    })?;
+    let song = generate_song(&settings);
+    for diagnostic in validate_song(&song, &StageDescription::default()).iter() {
+        println!("{}", diagnostic);
+    }
+    println!("Generated {}", song.name);
+    write_song(output, &song)
+}
+
+fn render_tool(input: &str, output: &str) -> Result<(), String> {
+    let song = read_playable_song(input)?;
+    let assets = application_root_dir()
+        .map_err(|err| format!("{}", err))?
+        .join("assets");
+    let instruments = load_instruments();
+    let drops = load_instrument(&assets, &instruments.foo);
+    let rewards = load_instrument(&assets, &instruments.note);
+    let buffer = render_song(&song, &drops, &rewards, RENDER_RATE);
+    println!(
+        "Rendered {} ({:.1} seconds)",
+        song.name,
+        buffer.len() as f32 / RENDER_RATE as f32
+    );
+    write_wav(Path::new(output), &buffer, RENDER_RATE)
+}
+
+// Stands on the spawn platform for a song, reporting what happens.
+fn simulate_tool(input: &str, beats: Option<&str>) -> Result<(), String> {
+    let song = read_playable_song(input)?;
+    let beats = match beats {
+        Some(beats) => beats
+            .parse::<i32>()
+            .map_err(|err| format!("Invalid beat count {}: {}", beats, err))?,
+        None => song.length().unwrap_or_else(|| song.cycle_length()),
+    };
+    let name = song.name.clone();
+    let duration = song.time_at(beats as f32) + 10.0;
+    let mut sim = Simulation::new(StageDescription::default(), vec![song]);
+    if !sim.start_song(10.0) {
+        return Err("Song never started".to_string());
+    }
+    let reached = sim.run_until_beat(beats, duration);
+    let stage = sim.stage();
+    let score = sim.score();
+    println!(
+        "{}: beat {}, {} misses, {} notes, {} points ({} dodges, best combo {}){}",
+        name,
+        stage.beat(),
+        stage.missed(),
+        stage.notes_found.len(),
+        score.points,
+        score.dodges,
+        score.best_combo,
+        if reached { "" } else { ", stopped early" }
+    );
+    Ok(())
+}
+
+fn analyze_tool(inputs: &[String]) -> Result<(), String> {
+    let stage = StageDescription::default();
+    let mut analyses = Vec::new();
+    for path in song_args(inputs).iter() {
+        let song = read_song(path)?;
+        let diagnostics = validate_song(&song, &stage);
+        if has_errors(&diagnostics) {
+            println!("{}: skipped, run check-songs for its errors", path);
+            continue;
+        }
+        let analysis = analyze(&song, &stage);
+        println!("{}", analysis);
+        analyses.push(analysis);
+    }
+    analyses.sort_by(|a, b| {
+        a.rating
+            .partial_cmp(&b.rating)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    if analyses.len() > 1 {
+        println!("Easiest to hardest:");
+        for analysis in analyses.iter() {
+            println!("  {:.1} {}", analysis.rating, analysis.name);
+        }
+    }
+    Ok(())
+}
+
+// Longest a looping song is played for before the bot gives up on it.
+const AUTOPLAY_LIMIT: f32 = 180.0;
+
+fn autoplay_tool(inputs: &[String]) -> Result<(), String> {
+    let mut failed = 0;
+    for input in inputs {
+        let song = read_playable_song(input)?;
+        let name = song.name.clone();
+        let duration = song.duration().unwrap_or(AUTOPLAY_LIMIT) + 10.0;
+        let mut sim = Simulation::new(StageDescription::default(), vec![song]);
+        sim.set_autoplay(true);
+        sim.run_until(duration, |sim| sim.records().song(&name).is_some());
+        let records = sim.records();
+        match records.song(&name) {
+            Some(record) if record.clears > 0 => println!(
+                "{}: cleared, {} misses, {} points",
+                name,
+                record.fewest_misses.unwrap_or(0),
+                record.best_score
+            ),
+            Some(record) => {
+                failed += 1;
+                println!(
+                    "{}: failed, {} notes, {} points",
+                    name, record.best_notes, record.best_score
+                );
+            }
+            None => {
+                failed += 1;
+                println!("{}: not finished after {:.0}s", name, sim.time());
+            }
+        }
+    }
+    if failed > 0 {
+        Err(format!("{} of {} songs not cleared", failed, inputs.len()))
+    } else {
+        Ok(())
+    }
+}
+
+fn usage() -> Result<(), String> {
+    Err("Usage:
+    import-midi <song.mid> <song.ron> [subdivision]
+    export-midi <out.mid> <song.ron|payout|lose>...
+    check-songs [song.ron]...
+    analyze [song.ron]...
+    generate <seed-key-bpm-difficulty> <song.ron>
+    render <song.ron> <out.wav>
+    simulate <song.ron> [beats]
+    autoplay <song.ron>..."
+        .to_string())
+}
+
+/// Runs a command-line tool instead of the game, if one was requested.
+pub fn run_tool(args: &[String]) -> Option<Result<(), String>> {
+    let command = args.get(1)?;
+    let arg = |index: usize| args.get(index).map(|arg| arg.as_str());
+    Some(match command.as_str() {
+        "import-midi" => match (arg(2), arg(3)) {
+            (Some(input), Some(output)) => import_midi_tool(input, output, arg(4)),
+            _ => usage(),
+        },
+        "export-midi" => match arg(2) {
+            Some(output) => export_midi_tool(output, &args[3..]),
+            _ => usage(),
+        },
+        "check-songs" => check_songs_tool(&args[2..]),
+        "analyze" => analyze_tool(&args[2..]),
+        "render" => match (arg(2), arg(3)) {
+            (Some(input), Some(output)) => render_tool(input, output),
+            _ => usage(),
+        },
+        "simulate" => match arg(2) {
+            Some(input) => simulate_tool(input, arg(3)),
+            _ => usage(),
+        },
+        "autoplay" => match arg(2) {
+            Some(_) => autoplay_tool(&args[2..]),
+            _ => usage(),
+        },
+        "generate" => match (arg(2), arg(3)) {
+            (Some(code), Some(output)) => generate_tool(code, output),
+            _ => usage(),
+        },
+        _ => return None,
+    })
+}
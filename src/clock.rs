@@ -0,0 +1,128 @@
+use crate::prelude::*;
+use amethyst::log::warn;
+use std::path::PathBuf;
+use std::time::Instant;
+
+/// Monotonic wall clock the song position is read from, so the song keeps time
+/// however long frames take.
+///
+/// It is not driven by the audio output. amethyst's `Output` plays each sound on
+/// its own sink and exposes no played-sample position, so following the output
+/// stream needs our own stream and is left as a follow-up. Until then the
+/// calibrated `Calibration::offset` is the only correction for output latency,
+/// and drift between this clock and the audio device isn't corrected.
+#[derive(Debug, Clone)]
+pub struct SongClock {
+    origin: Instant,
+    // Set while a replay drives the clock.
+    fixed: Option<f64>,
+    // Time the clock stopped at while the game is paused.
+    paused_at: Option<f64>,
+}
+
+impl Default for SongClock {
+    fn default() -> Self {
+        SongClock {
+            origin: Instant::now(),
+            fixed: None,
+            paused_at: None,
+        }
+    }
+}
+
+impl SongClock {
+    /// Seconds since the clock started.
+    pub fn now(&self) -> f64 {
+        self.fixed
+            .or(self.paused_at)
+            .unwrap_or_else(|| self.origin.elapsed().as_secs_f64())
+    }
+
+    /// Stops the clock until `resume`, so the song picks up where it left off.
+    pub fn pause(&mut self) {
+        if self.paused_at.is_none() {
+            self.paused_at = Some(self.now());
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if let Some(paused_at) = self.paused_at.take() {
+            let elapsed = std::time::Duration::from_secs_f64(paused_at.max(0.0));
+            self.origin = Instant::now().checked_sub(elapsed).unwrap_or(self.origin);
+        }
+    }
+
+    /// Moves the clock back by time gameplay skipped after a hitch. A replay's
+    /// times were recorded after the skip, so a pinned clock stays put.
+    pub fn skip(&mut self, seconds: f64) {
+        if self.fixed.is_some() {
+            return;
+        }
+        match self.paused_at.as_mut() {
+            Some(paused_at) => *paused_at -= seconds,
+            None => self.origin += std::time::Duration::from_secs_f64(seconds.max(0.0)),
+        }
+    }
+
+    /// Pins the clock to a time, or releases it back to real time with None.
+    pub fn set_fixed(&mut self, time: Option<f64>) {
+        if let (Some(fixed), None) = (self.fixed, time) {
+            // Carry on from the pinned time rather than jumping.
+            let elapsed = std::time::Duration::from_secs_f64(fixed.max(0.0));
+            self.origin = Instant::now().checked_sub(elapsed).unwrap_or(self.origin);
+        }
+        self.fixed = time;
+    }
+}
+
+/// Per-machine delay between starting a sound and the player hearing it.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Calibration {
+    /// Seconds. Ball drops are shown this much later than the song clock, so
+    /// they land when their note is heard.
+    pub offset: f32,
+}
+
+// Offsets beyond this are a bad calibration, not a real latency.
+const MAX_OFFSET: f32 = 0.5;
+
+impl Calibration {
+    fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("ld47").join("calibration.ron"))
+    }
+
+    /// The saved calibration, or None if this machine hasn't been calibrated.
+    pub fn load() -> Option<Self> {
+        let text = std::fs::read_to_string(Self::path()?).ok()?;
+        match ron::de::from_str::<Calibration>(&text) {
+            Ok(calibration) => Some(calibration),
+            Err(err) => {
+                warn!("Invalid calibration: {}", err);
+                None
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = Self::path().ok_or("No config directory")?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|err| format!("{}: {}", dir.display(), err))?;
+        }
+        let text = ron::ser::to_string_pretty(self, Default::default())
+            .map_err(|err| format!("{}", err))?;
+        std::fs::write(&path, text).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    /// Calibration from tap times measured against click times, using the median
+    /// so a few stray taps don't skew it.
+    pub fn from_taps(mut deltas: Vec<f32>) -> Option<Self> {
+        if deltas.is_empty() {
+            return None;
+        }
+        deltas.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let offset = deltas[deltas.len() / 2];
+        Some(Calibration {
+            offset: offset.max(-MAX_OFFSET).min(MAX_OFFSET),
+        })
+    }
+}
@@ -0,0 +1,313 @@
+use crate::analysis::analyze_song;
+use crate::music::{Song, Substructure, SUBNOTES};
+use crate::player::JUMP_SPEED;
+use crate::prelude::*;
+use crate::stage::{StageDescription, BALL_DROP_TIME, NOTES_TO_WIN};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::collections::HashMap;
+
+pub const KEYS: [&str; 12] = [
+    "c", "cs", "d", "ds", "e", "f", "fs", "g", "gs", "a", "as", "b",
+];
+const MAJOR: [usize; 7] = [0, 2, 4, 5, 7, 9, 11];
+const BAR: i32 = SUBNOTES * 4;
+const PHRASE_BARS: i32 = 2;
+// Phrases of the melody, by which fresh phrase they repeat: A B A C.
+const FORM: [usize; 4] = [0, 1, 0, 2];
+// Reward placements tried before settling for the one with the most reachable notes.
+const REWARD_ATTEMPTS: usize = 32;
+
+/// Everything a generated song depends on. Sharing the code reproduces the song.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratorSettings {
+    pub seed: u64,
+    pub key: usize,
+    pub bpm: i32,
+    pub difficulty: i32,
+}
+
+impl GeneratorSettings {
+    pub fn code(&self) -> String {
+        format!(
+            "{}-{}-{}-{}",
+            self.seed,
+            KEYS[self.key % KEYS.len()],
+            self.bpm,
+            self.difficulty
+        )
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        let parts = code.trim().split('-').collect::<Vec<&str>>();
+        if parts.len() != 4 {
+            return None;
+        }
+        Some(GeneratorSettings {
+            seed: parts[0].parse().ok()?,
+            key: KEYS
+                .iter()
+                .position(|key| *key == parts[1].to_lowercase())?,
+            bpm: parts[2].parse().ok()?,
+            difficulty: parts[3].parse().ok()?,
+        })
+    }
+}
+
+struct Difficulty {
+    durations: &'static [i32],
+    rest_chance: f32,
+    max_leap: i32,
+    rounds: i32,
+}
+
+fn difficulty(level: i32) -> Difficulty {
+    match level {
+        std::i32::MIN..=3 => Difficulty {
+            durations: &[8, 4, 4],
+            rest_chance: 0.3,
+            max_leap: 1,
+            rounds: 1,
+        },
+        4..=6 => Difficulty {
+            durations: &[8, 4, 4, 2],
+            rest_chance: 0.2,
+            max_leap: 2,
+            rounds: 2,
+        },
+        7..=8 => Difficulty {
+            durations: &[4, 2, 2, 1],
+            rest_chance: 0.1,
+            max_leap: 3,
+            rounds: 3,
+        },
+        _ => Difficulty {
+            durations: &[2, 2, 1, 1],
+            rest_chance: 0.05,
+            max_leap: 4,
+            rounds: 3,
+        },
+    }
+}
+
+fn key_notes(key: usize) -> Vec<Note> {
+    (0..NOTE_COUNT)
+        .filter(|note| MAJOR.contains(&((note + 12 - key % 12) % 12)))
+        .collect()
+}
+
+// A phrase is a rhythm with a scale degree for each note, or None for a rest.
+fn generate_phrase(
+    rng: &mut StdRng,
+    level: &Difficulty,
+    degree: &mut i32,
+    degrees: i32,
+) -> Vec<(i32, Option<i32>)> {
+    let mut phrase = Vec::new();
+    let mut offset = 0;
+    while offset < PHRASE_BARS * BAR {
+        if rng.gen::<f32>() < level.rest_chance && offset > 0 {
+            phrase.push((offset, None));
+        } else {
+            let leap = rng.gen_range(-level.max_leap, level.max_leap + 1);
+            *degree = (*degree + leap).max(0).min(degrees - 1);
+            phrase.push((offset, Some(*degree)));
+        }
+        offset += *level.durations.choose(rng).expect("Missing note duration");
+    }
+    phrase
+}
+
+// Voices other than the lead that drop on a beat.
+fn canon_notes_at(
+    melody: &HashMap<i32, Note>,
+    beat: i32,
+    rounds: i32,
+    repeat_at: i32,
+) -> Vec<Note> {
+    (1..rounds)
+        .filter_map(|voice| melody.get(&(beat - repeat_at * voice)).cloned())
+        .collect()
+}
+
+fn dodgeable(stage: &StageDescription, hits: &Vec<Note>) -> bool {
+    hits.iter().all(|hit| {
+        !stage
+            .neighbours(*hit)
+            .iter()
+            .all(|neighbour| hits.contains(neighbour))
+    })
+}
+
+fn place_rewards(
+    rng: &mut StdRng,
+    song: &Song,
+    notes: &Vec<Note>,
+    restart_at: i32,
+) -> HashMap<i32, Note> {
+    let lead = (BALL_DROP_TIME * song.bpm as f32 / 60.0 * song.subdivision as f32).ceil() as i32;
+    let mut candidates = notes.clone();
+    candidates.shuffle(rng);
+    let mut rewards = HashMap::new();
+    for index in 0..NOTES_TO_WIN as i32 {
+        let mut beat = restart_at * index / NOTES_TO_WIN as i32 + song.subdivision;
+        while beat < restart_at {
+            // Safe if nothing lands on the platform while the player runs over for it.
+            let safe = candidates.iter().position(|note| {
+                (beat - lead..beat + lead * 2)
+                    .all(|drop_beat| !song.get_notes_at(drop_beat).contains(note))
+            });
+            match safe {
+                Some(position) if !rewards.contains_key(&beat) => {
+                    rewards.insert(beat, candidates.remove(position));
+                    break;
+                }
+                _ => beat += song.subdivision,
+            }
+        }
+    }
+    rewards
+}
+
+fn payout(rewards: HashMap<i32, Note>, restart_at: i32, loops: i32) -> Substructure {
+    Substructure::Round {
+        notes: rewards,
+        rounds: 1,
+        repeat_at: restart_at,
+        restart_at,
+        pitch_up: 0,
+        loops: Some(loops),
+    }
+}
+
+// Places rewards again until enough of them to win can be reached by a player
+// who survives the first loop.
+fn place_reachable_rewards(
+    rng: &mut StdRng,
+    song: &Song,
+    stage: &StageDescription,
+    notes: &Vec<Note>,
+    restart_at: i32,
+) -> HashMap<i32, Note> {
+    let mut best: Option<(usize, HashMap<i32, Note>)> = None;
+    for _ in 0..REWARD_ATTEMPTS {
+        let rewards = place_rewards(rng, song, notes, restart_at);
+        let trial = Song {
+            payouts: vec![payout(rewards.clone(), restart_at, 1)],
+            ..song.clone()
+        };
+        let reachable = analyze_song(&trial, stage, JUMP_SPEED, BALL_DROP_TIME).reachable_rewards;
+        if reachable >= NOTES_TO_WIN {
+            return rewards;
+        }
+        if best.as_ref().map_or(true, |(most, _)| reachable > *most) {
+            best = Some((reachable, rewards));
+        }
+    }
+    best.map(|(_, rewards)| rewards).unwrap_or_default()
+}
+
+pub fn generate_song(settings: &GeneratorSettings) -> Song {
+    let mut rng = StdRng::seed_from_u64(settings.seed);
+    let level = difficulty(settings.difficulty);
+    let stage = StageDescription::default();
+    let notes = key_notes(settings.key);
+    let degrees = notes.len() as i32;
+
+    let mut degree = notes
+        .iter()
+        .position(|note| note % 12 == settings.key % 12)
+        .unwrap_or(0) as i32;
+    let mut phrases = Vec::new();
+    for _ in 0..3 {
+        phrases.push(generate_phrase(&mut rng, &level, &mut degree, degrees));
+    }
+
+    let repeat_at = PHRASE_BARS * BAR;
+    let mut melody = HashMap::new();
+    for (index, phrase) in FORM.iter().enumerate() {
+        let start = index as i32 * PHRASE_BARS * BAR;
+        for (offset, degree) in phrases[*phrase].iter() {
+            let beat = start + offset;
+            let degree = match degree {
+                Some(degree) => *degree,
+                None => continue,
+            };
+            let others = canon_notes_at(&melody, beat, level.rounds, repeat_at);
+            // Nudge notes that would collide with another voice, or rest.
+            let chosen = [0, 1, -1, 2, -2]
+                .iter()
+                .map(|shift| degree + shift)
+                .filter(|degree| *degree >= 0 && *degree < degrees)
+                .map(|degree| notes[degree as usize])
+                .find(|note| {
+                    let mut hits = others.clone();
+                    hits.push(*note);
+                    !others.contains(note) && dodgeable(&stage, &hits)
+                });
+            if let Some(note) = chosen {
+                melody.insert(beat, note);
+            }
+        }
+    }
+
+    let length = FORM.len() as i32 * PHRASE_BARS * BAR;
+    let restart_at = length + repeat_at * (level.rounds - 1);
+    let mut song = Song {
+        name: format!("Generated {}", settings.code()),
+        author: "Generator".to_string(),
+        bpm: settings.bpm.max(1),
+        structures: vec![Substructure::Round {
+            notes: melody,
+            rounds: level.rounds,
+            repeat_at,
+            restart_at,
+            pitch_up: 0,
+            loops: Some(2),
+        }],
+        ..Song::default()
+    };
+    let rewards = place_reachable_rewards(&mut rng, &song, &stage, &notes, restart_at);
+    let mut next_notes = rewards.values().cloned().collect::<Vec<Note>>();
+    next_notes.sort();
+    song.payouts = vec![payout(rewards, restart_at, 2)];
+    song.next_notes = next_notes;
+    song
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validate::{has_errors, validate_song};
+
+    #[test]
+    fn generated_songs_are_winnable_at_every_difficulty() {
+        let stage = StageDescription::default();
+        for difficulty in 1..=10 {
+            for seed in 0..4 {
+                let settings = GeneratorSettings {
+                    seed,
+                    key: seed as usize * 5,
+                    bpm: 120,
+                    difficulty,
+                };
+                let song = generate_song(&settings);
+                let diagnostics = validate_song(&song, &stage);
+                assert!(
+                    !has_errors(&diagnostics),
+                    "{} is invalid: {:?}",
+                    settings.code(),
+                    diagnostics
+                );
+                let analysis = analyze_song(&song, &stage, JUMP_SPEED, BALL_DROP_TIME);
+                assert!(
+                    analysis.reachable_rewards >= NOTES_TO_WIN,
+                    "{} has {} reachable rewards",
+                    settings.code(),
+                    analysis.reachable_rewards
+                );
+            }
+        }
+    }
+}
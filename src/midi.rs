@@ -0,0 +1,381 @@
+use crate::assets::SCALE;
+use crate::music::{MeterChange, Song, Substructure, TempoChange};
+use crate::prelude::*;
+use midly::{
+    num::{u15, u24, u28, u4, u7},
+    Format, Header, MetaMessage, MidiMessage, Smf, Timing, Track, TrackEvent, TrackEventKind,
+};
+use std::collections::HashMap;
+use std::fmt;
+
+// MIDI key of C4, the lowest note in SCALE.
+pub const MIDI_C4: i32 = 60;
+// Microseconds per quarter note when a file has no tempo event (120 bpm).
+const DEFAULT_TEMPO: u32 = 500_000;
+const EXPORT_TICKS_PER_BEAT: u64 = 480;
+const EXPORT_VELOCITY: u8 = 96;
+// Largest key, tempo in microseconds per quarter note and beats per bar MIDI can hold.
+const MAX_KEY: i32 = 127;
+const MAX_TEMPO: u32 = 0xff_ffff;
+const MAX_BEATS_PER_BAR: i32 = 255;
+// Round voices get channels 1 to 9. Channel 10 is for percussion.
+const MAX_VOICES: i32 = 9;
+
+#[derive(Debug, Clone)]
+pub enum ImportIssue {
+    Folded {
+        track: usize,
+        sub_beat: i32,
+        key: u8,
+        note: Note,
+    },
+    Dropped {
+        track: usize,
+        sub_beat: i32,
+        key: u8,
+    },
+}
+
+impl fmt::Display for ImportIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportIssue::Folded {
+                track,
+                sub_beat,
+                key,
+                note,
+            } => write!(
+                f,
+                "track {}, beat {}: folded key {} into {}",
+                track, sub_beat, key, SCALE[*note]
+            ),
+            ImportIssue::Dropped {
+                track,
+                sub_beat,
+                key,
+            } => write!(
+                f,
+                "track {}, beat {}: dropped key {}, beat already has a note",
+                track, sub_beat, key
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MidiImport {
+    pub song: Song,
+    pub issues: Vec<ImportIssue>,
+}
+
+fn fold_key(key: u8) -> (Note, bool) {
+    let mut note = key as i32 - MIDI_C4;
+    let folded = note < 0 || note >= NOTE_COUNT as i32;
+    while note < 0 {
+        note += 12;
+    }
+    while note >= NOTE_COUNT as i32 {
+        note -= 12;
+    }
+    (note as Note, folded)
+}
+
+fn quantize(tick: u64, ticks_per_beat: u64, subdivision: i32) -> i32 {
+    ((tick * subdivision as u64 + ticks_per_beat / 2) / ticks_per_beat) as i32
+}
+
+/// Reads a type 0 or 1 MIDI file, quantizing notes to `subdivision` sub-beats per beat.
+pub fn import_midi(bytes: &[u8], subdivision: i32) -> Result<MidiImport, String> {
+    let subdivision = subdivision.max(1);
+    let smf = Smf::parse(bytes).map_err(|err| format!("Invalid MIDI file: {}", err))?;
+    if smf.header.format == Format::Sequential {
+        return Err("Sequential (type 2) MIDI files are not supported".to_string());
+    }
+    let ticks_per_beat = match smf.header.timing {
+        Timing::Metrical(ticks) => ticks.as_int() as u64,
+        Timing::Timecode(..) => return Err("Timecode MIDI timing is not supported".to_string()),
+    };
+
+    let mut tempos = Vec::new();
+    let mut meters = Vec::new();
+    let mut name = String::new();
+    for track in smf.tracks.iter() {
+        let mut tick = 0;
+        for event in track.iter() {
+            tick += event.delta.as_int() as u64;
+            match event.kind {
+                TrackEventKind::Meta(MetaMessage::Tempo(microseconds)) => {
+                    tempos.push((tick, microseconds.as_int()));
+                }
+                TrackEventKind::Meta(MetaMessage::TimeSignature(numerator, denominator, ..)) => {
+                    meters.push(MeterChange {
+                        beat: quantize(tick, ticks_per_beat, subdivision),
+                        beats_per_bar: numerator as i32,
+                        beat_unit: 1 << denominator.min(6),
+                    });
+                }
+                TrackEventKind::Meta(MetaMessage::TrackName(track_name)) if name.is_empty() => {
+                    name = String::from_utf8_lossy(track_name).trim().to_string();
+                }
+                _ => {}
+            }
+        }
+    }
+    tempos.sort_by_key(|(tick, _)| *tick);
+    let to_bpm = |microseconds: u32| 60_000_000.0 / microseconds.max(1) as f32;
+    let bpm = (tempos
+        .first()
+        .map_or(to_bpm(DEFAULT_TEMPO), |(_, microseconds)| {
+            to_bpm(*microseconds)
+        })
+        .round() as i32)
+        .max(1);
+    let tempo_map = tempos
+        .iter()
+        .skip(1)
+        .map(|(tick, microseconds)| TempoChange {
+            beat: quantize(*tick, ticks_per_beat, subdivision),
+            bpm: to_bpm(*microseconds),
+            ramp: false,
+        })
+        .collect::<Vec<TempoChange>>();
+    meters.sort_by_key(|meter| meter.beat);
+    meters.retain(|meter| !(meter.beat == 0 && meter.beats_per_bar == 4 && meter.beat_unit == 4));
+
+    let mut issues = Vec::new();
+    let mut structures = Vec::new();
+    for (track_index, track) in smf.tracks.iter().enumerate() {
+        let mut notes = HashMap::new();
+        let mut tick = 0;
+        for event in track.iter() {
+            tick += event.delta.as_int() as u64;
+            if let TrackEventKind::Midi {
+                message: MidiMessage::NoteOn { key, vel },
+                ..
+            } = event.kind
+            {
+                if vel.as_int() == 0 {
+                    continue;
+                }
+                let key = key.as_int();
+                let sub_beat = quantize(tick, ticks_per_beat, subdivision);
+                let (note, folded) = fold_key(key);
+                if notes.contains_key(&sub_beat) {
+                    issues.push(ImportIssue::Dropped {
+                        track: track_index,
+                        sub_beat,
+                        key,
+                    });
+                    continue;
+                }
+                if folded {
+                    issues.push(ImportIssue::Folded {
+                        track: track_index,
+                        sub_beat,
+                        key,
+                        note,
+                    });
+                }
+                notes.insert(sub_beat, note);
+            }
+        }
+        if let Some(last) = notes.keys().max() {
+            let bar = subdivision * 4;
+            let restart_at = ((last / bar) + 1) * bar;
+            structures.push(Substructure::Round {
+                notes,
+                rounds: 1,
+                repeat_at: 0,
+                restart_at,
+                pitch_up: 0,
+                loops: Some(1),
+            });
+        }
+    }
+    if structures.is_empty() {
+        return Err("MIDI file has no notes".to_string());
+    }
+
+    Ok(MidiImport {
+        song: Song {
+            name,
+            bpm,
+            structures,
+            tempo_map,
+            meters,
+            subdivision,
+            ..Song::default()
+        },
+        issues,
+    })
+}
+
+fn to_track<'a>(mut events: Vec<(u64, TrackEventKind<'a>)>) -> Track<'a> {
+    // Note offs sort ahead of note ons on the same tick.
+    events.sort_by_key(|(tick, kind)| {
+        (
+            *tick,
+            match kind {
+                TrackEventKind::Midi {
+                    message: MidiMessage::NoteOn { .. },
+                    ..
+                } => 1,
+                _ => 0,
+            },
+        )
+    });
+    let mut last_tick = 0;
+    let mut track = Vec::new();
+    for (tick, kind) in events {
+        track.push(TrackEvent {
+            delta: u28::new((tick - last_tick) as u32),
+            kind,
+        });
+        last_tick = tick;
+    }
+    track.push(TrackEvent {
+        delta: u28::new(0),
+        kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+    });
+    track
+}
+
+// Ticks from the start of a song to a sub-beat position, rounded so subdivisions
+// that don't divide a beat's ticks stay in time.
+fn ticks_at(song: &Song, position: f32) -> u64 {
+    (position as f64 * EXPORT_TICKS_PER_BEAT as f64 / song.subdivision.max(1) as f64).round() as u64
+}
+
+fn note_events<'a>(
+    events: &mut Vec<(u64, TrackEventKind<'a>)>,
+    song: &Song,
+    structure: &Substructure,
+    start: u64,
+    length: i32,
+) -> Result<(), String> {
+    for beat in 0..length {
+        for (voice, note) in structure.get_voices_at(beat, song.subdivision) {
+            let tick = start + ticks_at(song, song.swung_position(beat));
+            if voice < 0 || voice >= MAX_VOICES {
+                return Err(format!(
+                    "{}: round voice {} on sub-beat {} has no MIDI channel of its own",
+                    song.name,
+                    voice + 1,
+                    beat
+                ));
+            }
+            let channel = u4::new(voice as u8);
+            let key = MIDI_C4 + note as i32;
+            if key > MAX_KEY {
+                return Err(format!(
+                    "{}: note {} on sub-beat {} is out of MIDI's range",
+                    song.name, note, beat
+                ));
+            }
+            let key = u7::new(key as u8);
+            events.push((
+                tick,
+                TrackEventKind::Midi {
+                    channel,
+                    message: MidiMessage::NoteOn {
+                        key,
+                        vel: u7::new(EXPORT_VELOCITY),
+                    },
+                },
+            ));
+            events.push((
+                start + ticks_at(song, song.swung_position(beat) + 1.0),
+                TrackEventKind::Midi {
+                    channel,
+                    message: MidiMessage::NoteOff {
+                        key,
+                        vel: u7::new(0),
+                    },
+                },
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Writes songs back to back as a type 1 MIDI file, one track per structure.
+/// Each round voice plays on its own channel. Fails on notes, tempos, time
+/// signatures or voices MIDI can't hold.
+pub fn export_session<'a>(songs: &'a [Song]) -> Result<Smf<'a>, String> {
+    let mut smf = Smf::new(Header::new(
+        Format::Parallel,
+        Timing::Metrical(u15::new(EXPORT_TICKS_PER_BEAT as u16)),
+    ));
+    let mut tempo_events = Vec::new();
+    let mut structure_tracks = Vec::new();
+    let mut start = 0;
+    for song in songs.iter() {
+        let length = song.length().unwrap_or_else(|| song.cycle_length());
+        let mut last_tempo = None;
+        let mut last_meter = None;
+        for beat in 0..length.max(1) {
+            let tick = start + ticks_at(song, beat as f32);
+            let bpm = song.bpm_at(beat as f32);
+            let tempo = 60_000_000.0 / bpm;
+            if !(tempo >= 1.0 && tempo <= MAX_TEMPO as f32) {
+                return Err(format!(
+                    "{}: tempo of {} bpm on sub-beat {} is out of MIDI's range",
+                    song.name, bpm, beat
+                ));
+            }
+            let tempo = tempo as u32;
+            if last_tempo != Some(tempo) {
+                tempo_events.push((
+                    tick,
+                    TrackEventKind::Meta(MetaMessage::Tempo(u24::new(tempo))),
+                ));
+                last_tempo = Some(tempo);
+            }
+            let meter = song.meter_at(beat);
+            let meter = (meter.beats_per_bar, meter.beat_unit);
+            if last_meter != Some(meter) {
+                if meter.0 < 1
+                    || meter.0 > MAX_BEATS_PER_BAR
+                    || meter.1 < 1
+                    || !(meter.1 as u32).is_power_of_two()
+                {
+                    return Err(format!(
+                        "{}: time signature {}/{} on sub-beat {} is out of MIDI's range",
+                        song.name, meter.0, meter.1, beat
+                    ));
+                }
+                let denominator = (meter.1 as u32).trailing_zeros() as u8;
+                tempo_events.push((
+                    tick,
+                    TrackEventKind::Meta(MetaMessage::TimeSignature(
+                        meter.0 as u8,
+                        denominator,
+                        24,
+                        8,
+                    )),
+                ));
+                last_meter = Some(meter);
+            }
+        }
+        tempo_events.push((
+            start,
+            TrackEventKind::Meta(MetaMessage::Marker(song.name.as_bytes())),
+        ));
+        for (index, structure) in song.structures.iter().enumerate() {
+            if structure_tracks.len() <= index {
+                structure_tracks.push(Vec::new());
+            }
+            note_events(&mut structure_tracks[index], song, structure, start, length)?;
+        }
+        start += ticks_at(song, length as f32);
+    }
+    smf.tracks.push(to_track(tempo_events));
+    for events in structure_tracks {
+        smf.tracks.push(to_track(events));
+    }
+    Ok(smf)
+}
+
+pub fn export_song(song: &Song) -> Result<Smf, String> {
+    export_session(std::slice::from_ref(song))
+}
@@ -1,105 +1,365 @@
 use crate::prelude::*;
+use amethyst::assets::{Asset, Handle};
 use std::collections::HashMap;
 
 pub type Note = usize;
 pub const SUBNOTES: i32 = 4;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Song {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub author: String,
     pub bpm: i32,
     pub structures: Vec<Substructure>,
+    #[serde(default)]
     pub payouts: Vec<Substructure>,
+    #[serde(default)]
     pub next_notes: Vec<Note>,
+    #[serde(default)]
+    pub tempo_map: Vec<TempoChange>,
+    #[serde(default)]
+    pub meters: Vec<MeterChange>,
+    /// Sub-beats per beat. 3 or 6 charts triplets.
+    #[serde(default = "default_subdivision")]
+    pub subdivision: i32,
+    /// How far every odd sub-beat is pushed back, as a fraction of a sub-beat.
+    /// 0.0 is straight, 0.33 swings pairs of sub-beats like triplets.
+    #[serde(default)]
+    pub swing: f32,
+    /// Fraction of the charted tempo the song plays at, for practice.
+    #[serde(skip, default = "default_tempo_scale")]
+    pub tempo_scale: f32,
+}
+
+fn default_subdivision() -> i32 {
+    SUBNOTES
+}
+
+fn default_tempo_scale() -> f32 {
+    1.0
+}
+
+impl Default for Song {
+    fn default() -> Self {
+        Song {
+            name: String::new(),
+            author: String::new(),
+            bpm: 0,
+            structures: vec![],
+            payouts: vec![],
+            next_notes: vec![],
+            tempo_map: vec![],
+            meters: vec![],
+            subdivision: SUBNOTES,
+            swing: 0.0,
+            tempo_scale: 1.0,
+        }
+    }
+}
+
+/// Sets the tempo from a sub-beat onward. With `ramp`, the tempo instead changes
+/// steadily from the previous marker until it reaches `bpm` here.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TempoChange {
+    pub beat: i32,
+    pub bpm: f32,
+    #[serde(default)]
+    pub ramp: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MeterChange {
+    pub beat: i32,
+    pub beats_per_bar: i32,
+    pub beat_unit: i32,
+}
+
+impl MeterChange {
+    pub fn bar_length(&self, subdivision: i32) -> i32 {
+        (self.beats_per_bar * subdivision * 4 / self.beat_unit.max(1)).max(1)
+    }
+}
+
+struct TempoSegment {
+    start: f32,
+    end: Option<f32>,
+    bpm: f32,
+    end_bpm: f32,
+    subdivision: f32,
+}
+
+impl TempoSegment {
+    // Sub-beats per second change linearly with beats across a ramp.
+    fn slope(&self) -> Option<f32> {
+        match self.end {
+            Some(end) if end > self.start && self.end_bpm != self.bpm => {
+                Some((self.end_bpm - self.bpm) / (end - self.start))
+            }
+            _ => None,
+        }
+    }
+
+    fn time_for(&self, beats: f32) -> f32 {
+        match self.slope() {
+            Some(slope) => {
+                60.0 / self.subdivision * f32::ln((self.bpm + slope * beats) / self.bpm) / slope
+            }
+            None => beats * 60.0 / (self.subdivision * self.bpm),
+        }
+    }
+
+    fn beats_for(&self, time: f32) -> f32 {
+        match self.slope() {
+            Some(slope) => {
+                self.bpm / slope * (f32::exp(time * self.subdivision * slope / 60.0) - 1.0)
+            }
+            None => time * self.subdivision * self.bpm / 60.0,
+        }
+    }
+
+    fn duration(&self) -> Option<f32> {
+        self.end.map(|end| self.time_for(end - self.start))
+    }
+}
+
+impl Asset for Song {
+    const NAME: &'static str = "ld47::Song";
+    type Data = Self;
+    type HandleStorage = VecStorage<Handle<Self>>;
 }
 
 impl Song {
     pub fn get_notes_at(&self, beat: i32) -> Vec<Note> {
         let mut notes = Vec::new();
         for structure in self.structures.iter() {
-            notes.append(&mut structure.get_notes_at(beat));
+            notes.append(&mut structure.get_notes_at(beat, self.subdivision));
         }
         notes
     }
     pub fn get_rewards_at(&self, beat: i32, paid_out: &Vec<Note>) -> Vec<Note> {
         let mut payouts = Vec::new();
         for payout in self.payouts.iter() {
-            payouts.append(&mut payout.get_notes_at(beat));
+            payouts.append(&mut payout.get_notes_at(beat, self.subdivision));
         }
         payouts.retain(|note| !paid_out.contains(note));
         payouts
     }
-    pub fn done(&self, beat: i32) -> bool {
-        let nominal_beat = beat - ((self.bpm / 60) * 4) - 8;
+    pub fn cycle_length(&self) -> i32 {
+        self.structures
+            .iter()
+            .map(|structure| structure.cycle_length(self.subdivision))
+            .max()
+            .unwrap_or(0)
+    }
+    /// Sub-beats until the last structure ends, or None if any structure loops forever.
+    pub fn length(&self) -> Option<i32> {
+        let mut length = 0;
         for structure in self.structures.iter() {
-            if !structure.done(nominal_beat) {
-                return false;
+            length = length.max(structure.length(self.subdivision)?);
+        }
+        Some(length)
+    }
+    pub fn duration(&self) -> Option<f32> {
+        self.length().map(|length| self.time_at(length as f32))
+    }
+
+    fn tempo_segments(&self) -> Vec<TempoSegment> {
+        let mut changes = self
+            .tempo_map
+            .iter()
+            .filter(|change| change.beat > 0 && change.bpm > 0.0)
+            .collect::<Vec<&TempoChange>>();
+        changes.sort_by_key(|change| change.beat);
+        let subdivision = self.subdivision.max(1) as f32;
+        let bpm = self.bpm.max(1) as f32 * self.tempo_scale;
+        let mut segments = vec![TempoSegment {
+            start: 0.0,
+            end: None,
+            bpm,
+            end_bpm: bpm,
+            subdivision,
+        }];
+        for change in changes {
+            let bpm = change.bpm * self.tempo_scale;
+            let last = segments.last_mut().expect("Missing tempo segment");
+            last.end = Some(change.beat as f32);
+            if change.ramp {
+                last.end_bpm = bpm;
+            }
+            segments.push(TempoSegment {
+                start: change.beat as f32,
+                end: None,
+                bpm,
+                end_bpm: bpm,
+                subdivision,
+            });
+        }
+        segments
+    }
+
+    /// Sub-beat position at a time in seconds, following the tempo map.
+    pub fn sub_beat_at(&self, time: f32) -> f32 {
+        let segments = self.tempo_segments();
+        if time <= 0.0 {
+            return segments[0].beats_for(time);
+        }
+        let mut remaining = time;
+        for segment in segments.iter() {
+            match segment.duration() {
+                Some(duration) if remaining >= duration => remaining -= duration,
+                _ => return segment.start + segment.beats_for(remaining),
             }
         }
-        true
+        0.0
     }
-}
 
-pub const SONG_COUNT: usize = 5;
+    /// Time in seconds at which a sub-beat is reached.
+    pub fn time_at(&self, beat: f32) -> f32 {
+        let segments = self.tempo_segments();
+        if beat <= 0.0 {
+            return segments[0].time_for(beat);
+        }
+        let mut time = 0.0;
+        for segment in segments.iter() {
+            match segment.end {
+                Some(end) if beat >= end => time += segment.time_for(end - segment.start),
+                _ => return time + segment.time_for(beat - segment.start),
+            }
+        }
+        time
+    }
 
-impl Song {
-    pub fn row_your_boat() -> Self {
-        Song {
-            bpm: 48,
-            structures: vec![Substructure::row_your_boat()],
-            payouts: vec![Substructure::reward_row_row()],
-            next_notes: vec![C4, D4, E4, F4, G4, A4, B4, C5],
+    /// Position of a sub-beat once swing is applied.
+    pub fn swung_position(&self, beat: i32) -> f32 {
+        if beat % 2 != 0 {
+            beat as f32 + self.swing
+        } else {
+            beat as f32
         }
     }
-    pub fn alouette() -> Self {
-        Song {
-            bpm: 120,
-            structures: vec![Substructure::alouette()],
-            payouts: vec![Substructure::reward_alouette()],
-            next_notes: vec![C4, D4, E4, F4, G4, A4, B4, C5],
+
+    /// The latest sub-beat that has been reached at a time in seconds.
+    pub fn beat_at(&self, time: f32) -> i32 {
+        let position = self.sub_beat_at(time);
+        let beat = position.floor() as i32;
+        if position < self.swung_position(beat) {
+            beat - 1
+        } else {
+            beat
         }
     }
-    pub fn c_scale() -> Self {
-        Song {
-            bpm: 240,
-            structures: vec![Substructure::c_scales()],
-            payouts: vec![Substructure::reward_c_scale()],
-            next_notes: vec![C4, D4, E4, F4, G4, A4, B4, C5],
+
+    /// Time in seconds at which a sub-beat's notes drop, including swing.
+    pub fn time_of_beat(&self, beat: i32) -> f32 {
+        self.time_at(self.swung_position(beat))
+    }
+
+    pub fn bpm_at(&self, beat: f32) -> f32 {
+        for segment in self.tempo_segments() {
+            if segment.end.map_or(true, |end| beat < end) {
+                return match segment.slope() {
+                    Some(slope) => segment.bpm + slope * (beat - segment.start).max(0.0),
+                    None => segment.bpm,
+                };
+            }
         }
+        self.bpm as f32 * self.tempo_scale
     }
-    pub fn coffee() -> Self {
-        Song {
-            bpm: 240,
-            structures: vec![Substructure::coffee()],
-            payouts: vec![Substructure::reward_coffee()],
-            next_notes: vec![C4, D4, E4, F4, G4, A4, B4, C5],
+
+    fn meter_changes(&self) -> Vec<MeterChange> {
+        let mut meters = vec![MeterChange {
+            beat: 0,
+            beats_per_bar: 4,
+            beat_unit: 4,
+        }];
+        let mut changes = self.meters.clone();
+        changes.sort_by_key(|meter| meter.beat);
+        meters.extend(changes.into_iter().filter(|meter| meter.beat >= 0));
+        meters
+    }
+
+    pub fn meter_at(&self, beat: i32) -> MeterChange {
+        self.meter_changes()
+            .into_iter()
+            .filter(|meter| meter.beat <= beat)
+            .last()
+            .expect("Missing meter")
+    }
+
+    /// The bar a sub-beat falls in, counting from 0, and the sub-beat within that bar.
+    pub fn bar_at(&self, beat: i32) -> (i32, i32) {
+        let meters = self.meter_changes();
+        let mut bar = 0;
+        for (index, meter) in meters.iter().enumerate() {
+            let length = meter.bar_length(self.subdivision);
+            match meters.get(index + 1) {
+                Some(next) if beat >= next.beat => {
+                    bar += (next.beat - meter.beat + length - 1) / length;
+                }
+                _ => {
+                    let offset = (beat - meter.beat).max(0);
+                    return (bar + offset / length, offset % length);
+                }
+            }
         }
+        (bar, 0)
     }
-    pub fn donkeys() -> Self {
-        Song {
-            bpm: 72,
-            structures: vec![Substructure::donkeys()],
-            payouts: vec![Substructure::reward_donkeys()],
-            next_notes: vec![C4, D4, E4, F4, G4, A4, B4, C5],
+
+    /// Sub-beat at which a bar, counting from 0, starts.
+    pub fn bar_start(&self, bar: i32) -> i32 {
+        let meters = self.meter_changes();
+        let mut bars_before = 0;
+        for (index, meter) in meters.iter().enumerate() {
+            let length = meter.bar_length(self.subdivision);
+            match meters.get(index + 1) {
+                Some(next) => {
+                    let bars = (next.beat - meter.beat + length - 1) / length;
+                    if bar < bars_before + bars {
+                        return meter.beat + (bar - bars_before) * length;
+                    }
+                    bars_before += bars;
+                }
+                None => return meter.beat + (bar - bars_before) * length,
+            }
+        }
+        0
+    }
+    /// How far through the song a beat is, or through the current cycle for endless songs.
+    pub fn progress(&self, beat: i32) -> f32 {
+        let length = self.length().unwrap_or_else(|| self.cycle_length());
+        if length <= 0 || beat < 0 {
+            0.0
+        } else if self.length().is_some() {
+            f32::min(beat as f32 / length as f32, 1.0)
+        } else {
+            (beat % length) as f32 / length as f32
         }
     }
-    pub fn songs() -> [Song; SONG_COUNT] {
-        [
-            Song::row_your_boat(),
-            Song::c_scale(),
-            Song::alouette(),
-            Song::coffee(),
-            Song::donkeys(),
-        ]
+    pub fn finished(&self, beat: i32) -> bool {
+        self.length().map_or(false, |length| beat >= length)
+    }
+
+    /// The song at a fraction of its tempo.
+    pub fn with_tempo(&self, multiplier: f32) -> Self {
+        Song {
+            tempo_scale: self.tempo_scale * multiplier,
+            ..self.clone()
+        }
     }
+}
+
+impl Song {
     pub fn payout_song(notes: &Vec<Note>) -> Self {
         Song {
+            name: "Payout".to_string(),
             bpm: 300,
             structures: vec![Substructure::Scale {
                 notes: notes.clone(),
                 interval: 4,
             }],
-            payouts: vec![],
-            next_notes: vec![],
+            ..Song::default()
         }
     }
     pub fn lose_song() -> Self {
@@ -107,17 +367,16 @@ impl Song {
             0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19,
         ];
         rand_shuffle(&mut notes);
-        println!("{:?}", notes);
         Song {
+            name: "Lose".to_string(),
             bpm: 300,
             structures: vec![Substructure::Scale { notes, interval: 4 }],
-            payouts: vec![],
-            next_notes: vec![],
+            ..Song::default()
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum Substructure {
     Round {
         notes: HashMap<i32, Note>,
@@ -125,442 +384,86 @@ pub enum Substructure {
         repeat_at: i32,
         restart_at: i32,
         pitch_up: usize,
+        #[serde(default)]
+        loops: Option<i32>,
     },
     Scale {
         notes: Vec<Note>,
         interval: i32,
     },
+    Chord {
+        notes: HashMap<i32, Vec<Note>>,
+        restart_at: i32,
+        #[serde(default)]
+        loops: Option<i32>,
+    },
+    Arpeggio {
+        notes: Vec<Note>,
+        start: i32,
+        rate: i32,
+        direction: ArpeggioDirection,
+        #[serde(default = "default_arpeggio_loops")]
+        loops: i32,
+    },
+    Ostinato {
+        notes: HashMap<i32, Note>,
+        start: i32,
+        /// Sub-beats in a bar, or a 4/4 bar at the song's subdivision if left out.
+        #[serde(default)]
+        bar_length: Option<i32>,
+        bars: i32,
+    },
 }
 
-impl Substructure {
-    fn row_your_boat() -> Self {
-        let mut notes = HashMap::new();
-        // C D E F G A B  C  D  E  F  G
-        // 0 2 4 5 7 9 11 12 14 16 17 19
-        let progression = [
-            (0, C4), // Row
-            (3, C4), // Row
-            (6, C4), // Row
-            (8, D4),
-            (9, E4),
-            (12, E4), // Gently/Round 2
-            (14, D4),
-            (15, E4),
-            (17, F4),
-            (18, G4), // Stream
-            (24, C5), // Merrily
-            (25, C5),
-            (26, C5),
-            (27, G4), // Merrily
-            (28, G4),
-            (29, G4),
-            (30, E4), // Merrily
-            (31, E4),
-            (32, E4),
-            (33, C4), // Merrily
-            (34, C4),
-            (35, C4),
-            (36, G4), // Life
-            (38, F4), // Is
-            (39, E4), // But
-            (41, D4), // A
-            (42, C4), // Dream
-        ];
-        for (beat, note) in progression.iter() {
-            notes.insert(*beat, *note);
-        }
-        let rounds = 2;
-        let repeat_at = 12;
-        let restart_at = 57;
-        let pitch_up = 0;
-        Substructure::Round {
-            notes,
-            rounds,
-            repeat_at,
-            pitch_up,
-            restart_at,
-        }
-    }
-    fn alouette() -> Self {
-        let mut notes = HashMap::new();
-        // C D E F G A B  C  D  E  F  G
-        // 0 2 4 5 7 9 11 12 14 16 17 19
-        let progression = [
-            // Measure 1
-            (0, F4),  // A
-            (6, G4),  // lou
-            (8, A4),  // et
-            (12, A4), // te
-            // Measure 2
-            (16, G4), // gen
-            (18, F4), // tille
-            (20, G4), // A
-            (22, A4), // lou
-            (24, F4), // et
-            (28, C4), // te
-            // Measure 3
-            (32, F4), // A
-            (38, G4), // lou
-            (40, A4), // et
-            (44, A4), // te
-            // Measure 4
-            (48, G4), // je
-            (50, F4), // te
-            (52, G4), // plu
-            (54, A4), // mer
-            (56, F4), // ai
-            // Measure 5
-            (64, F4), // je
-            (66, E4), // te
-            (68, F4), // plu
-            (70, E4), // mer
-            (72, F4), // ai
-            (74, A4), // la
-            (76, C5), // tete
-            // Measure 6
-            (80, C5),     // je
-            (82, D5),     // te
-            (84, C5),     // plu
-            (86, B4 + 1), // mer
-            (88, A4),     // ai
-            (90, G4),     // la
-            (92, F4),     // tete
-            // Measure 7
-            (96, C5),  // Et
-            (98, C5),  // le
-            (100, C5), // tete
-            (104, C4), // Et
-            (106, C4), // la
-            (108, C4), // tete
-            // Measure 8
-            (112, C5), // Oh
-            (114, C5), // Oh
-            (116, C5), // Oh
-            (118, C5), // Oh
-            (120, C5), // Oh
-            (122, C5), // Oh
-            (124, C5), // Oh
-            (126, C5), // Oh
-        ];
-        for (beat, note) in progression.iter() {
-            notes.insert(*beat, *note);
-        }
-        let rounds = 2;
-        let repeat_at = 64;
-        let restart_at = 128 + 64;
-        let pitch_up = 0;
-        Substructure::Round {
-            notes,
-            rounds,
-            repeat_at,
-            pitch_up,
-            restart_at,
-        }
-    }
-
-    fn c_scales() -> Self {
-        let mut notes = HashMap::new();
-        // C D E F G A B  C  D  E  F  G
-        // 0 2 4 5 7 9 11 12 14 16 17 19
-        let progression = [
-            (0, C4),
-            (4, D4),
-            (8, E4),
-            (12, F4),
-            (16, G4),
-            (20, A4),
-            (24, B4),
-            (28, C5),
-            (32, B4),
-            (36, A4),
-            (40, G4),
-            (44, F4),
-            (48, E4),
-            (52, D4),
-            (56, C4),
-        ];
-        for (beat, note) in progression.iter() {
-            notes.insert(*beat, *note);
-        }
-        let rounds = 3;
-        let repeat_at = 28;
-        let restart_at = 60 + 84;
-        let pitch_up = 4;
-        Substructure::Round {
-            notes,
-            rounds,
-            repeat_at,
-            pitch_up,
-            restart_at,
-        }
-    }
-
-    fn reward_c_scale() -> Self {
-        let mut notes = HashMap::new();
-        let progression = [
-            (12, C5),
-            (36, B4),
-            (60, A4),
-            (74, G4),
-            (98, F4),
-            (112, E4),
-            (124, D4),
-            (136, C4),
-        ];
-        for (beat, note) in progression.iter() {
-            notes.insert(*beat, *note);
-        }
-        let rounds = 1;
-        let repeat_at = 144;
-        let restart_at = 144;
-        let pitch_up = 0;
-        Substructure::Round {
-            notes,
-            rounds,
-            repeat_at,
-            pitch_up,
-            restart_at,
-        }
-    }
-
-    fn coffee() -> Self {
-        let mut notes = HashMap::new();
-        // C D E F G A B  C  D  E  F  G
-        // 0 2 4 5 7 9 11 12 14 16 17 19
-        let progression = [
-            (0, C5),  // C
-            (4, A4),  // O
-            (8, F4),  // F
-            (12, F4), // F
-            (16, E4), // E
-            (20, E4), // E
-            (24, E4), // Not
-            (28, G4), // Tea
-            (32, E4), // But
-            (36, F4), // Cof
-            (38, E4), // fee
-            (40, F4), // is
-            (42, G4), // for
-            (44, F4), // me
-            // Round 2
-            (48, A4),     // Cof
-            (50, A4),     // fee
-            (52, C5),     // mo
-            (54, C5),     // cha
-            (56, A4),     // Cof
-            (58, A4),     // fee
-            (60, B4 - 1), // la
-            (62, A4),     // tte
-            (64, B4 - 1), // Cof
-            (66, C5),     // fee
-            (68, B4 - 1), // black
-            // Next verse
-            (72, G4),     // Are
-            (74, G4),     // you
-            (76, B4 - 1), // gro
-            (78, B4 - 1), // ggy
-            (80, G4),     // Cof
-            (82, G4),     // fee
-            (84, A4),     // gives
-            (86, G4),     // you
-            (88, A4),     // what
-            (90, B4 - 1), // you
-            (92, A4),     // lack!
-            // Round 3 and final verse
-            (96, F4),  // When
-            (100, F4), // i
-            (104, F4), // am
-            (108, G4), // feel
-            (112, G4), // ing
-            (116, G4), // slow
-            (120, C4), // Time
-            (124, C4), // for
-            (128, C5), // a
-            (132, C5), // cup
-            (136, C4), // of
-            (140, F4), // Joe!
-        ];
-        for (beat, note) in progression.iter() {
-            notes.insert(*beat, *note);
-        }
-        let rounds = 3;
-        let repeat_at = 48;
-        let restart_at = 48 * 5;
-        let pitch_up = 0;
-        Substructure::Round {
-            notes,
-            rounds,
-            repeat_at,
-            pitch_up,
-            restart_at,
-        }
-    }
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+pub enum ArpeggioDirection {
+    Up,
+    Down,
+    UpDown,
+}
 
-    fn reward_coffee() -> Self {
-        let mut notes = HashMap::new();
-        let progression = [
-            (32, C4),
-            (64, E4),
-            (96, F4),
-            (128, G4 + 1),
-            (160, A4),
-            (192, B4 - 1),
-            (224, C5),
-            (232, F5),
-        ];
-        for (beat, note) in progression.iter() {
-            notes.insert(*beat, *note);
-        }
-        let rounds = 1;
-        let repeat_at = 240;
-        let restart_at = 240;
-        let pitch_up = 0;
-        Substructure::Round {
-            notes,
-            rounds,
-            repeat_at,
-            pitch_up,
-            restart_at,
-        }
-    }
+fn default_arpeggio_loops() -> i32 {
+    1
+}
 
-    fn donkeys() -> Self {
-        let mut notes = HashMap::new();
-        // C D E F G A B  C  D  E  F  G
-        // 0 2 4 5 7 9 11 12 14 16 17 19
-        let progression = [
-            // Round 1
-            (0, E4),      // Don
-            (2, E4),      // keys
-            (4, E4),      // are
-            (6, F4 + 1),  // in
-            (8, G4 + 1),  // love
-            (10, F4 + 1), // with
-            (12, E4),     // car
-            (14, C4),     // rots
-            // Round 2
-            (16, G4 + 1), // Car
-            (18, G4 + 1), // rots
-            (20, G4 + 1), // aren't
-            (22, A4),     // in
-            (24, B4),     // love
-            (26, A4),     // at
-            (28, G4 + 1), // all
-            // Round 3
-            (32, E5), // all
-            (36, B4), // all
-            (40, E5), // all
-            (44, B4), // all
-            // Round 4
-            (48, B4),     // Car
-            (50, C5 + 1), // rots
-            (52, B4),     // aren't
-            (54, A4),     // in
-            (56, G4 + 1), // love
-            (58, F4 + 1), // at
-            (60, E4),     // all
-        ];
-        for (beat, note) in progression.iter() {
-            notes.insert(*beat, *note);
-        }
-        let rounds = 4;
-        let repeat_at = 16;
-        let restart_at = 16 * 7;
-        let pitch_up = 0;
-        Substructure::Round {
-            notes,
-            rounds,
-            repeat_at,
-            pitch_up,
-            restart_at,
-        }
-    }
+/// Sub-beats in an ostinato's bar, in a song of `subdivision` sub-beats per beat.
+pub fn ostinato_bar_length(bar_length: Option<i32>, subdivision: i32) -> i32 {
+    bar_length.unwrap_or(subdivision * 4)
+}
 
-    fn reward_donkeys() -> Self {
-        let mut notes = HashMap::new();
-        let progression = [
-            (12, E4),
-            (24, F4 + 1),
-            (36, G4 + 1),
-            (48, A4),
-            (60, C4 + 1),
-            (72, C4),
-            (84, E5),
-            (96, B4),
-        ];
-        for (beat, note) in progression.iter() {
-            notes.insert(*beat, *note);
-        }
-        let rounds = 1;
-        let repeat_at = 112;
-        let restart_at = 112;
-        let pitch_up = 0;
-        Substructure::Round {
-            notes,
-            rounds,
-            repeat_at,
-            pitch_up,
-            restart_at,
+fn spell_arpeggio(notes: &Vec<Note>, direction: ArpeggioDirection) -> Vec<Note> {
+    let mut up = notes.clone();
+    up.sort();
+    match direction {
+        ArpeggioDirection::Up => up,
+        ArpeggioDirection::Down => up.into_iter().rev().collect(),
+        ArpeggioDirection::UpDown => {
+            let mut spelled = up.clone();
+            if up.len() > 2 {
+                spelled.extend(up[1..up.len() - 1].iter().rev());
+            }
+            spelled
         }
     }
+}
 
-    fn reward_row_row() -> Self {
-        let mut notes = HashMap::new();
-        let progression = [
-            (6, C4),
-            (12, D4),
-            (18, E4),
-            (24, F4),
-            (30, G4),
-            (36, A4),
-            (42, B4),
-            (48, C5),
-        ];
-        for (beat, note) in progression.iter() {
-            notes.insert(*beat, *note);
-        }
-        let rounds = 1;
-        let repeat_at = 57;
-        let restart_at = 57;
-        let pitch_up = 0;
-        Substructure::Round {
-            notes,
-            rounds,
-            repeat_at,
-            pitch_up,
-            restart_at,
-        }
+impl Substructure {
+    pub fn get_notes_at(&self, beat: i32, subdivision: i32) -> Vec<Note> {
+        self.get_voices_at(beat, subdivision)
+            .into_iter()
+            .map(|(_voice, note)| note)
+            .collect()
     }
 
-    fn reward_alouette() -> Self {
-        let mut notes = HashMap::new();
-        let progression = [
-            (16, C4),
-            (48, D4),
-            (64, E4),
-            (80, F4),
-            (96, G4),
-            (128, A4),
-            (144, B4),
-            (160, C5),
-        ];
-        for (beat, note) in progression.iter() {
-            notes.insert(*beat, *note);
+    /// Notes at a beat, paired with the round voice that plays them. Structures
+    /// that would divide by zero play nothing.
+    pub fn get_voices_at(&self, beat: i32, subdivision: i32) -> Vec<(i32, Note)> {
+        if self
+            .length(subdivision)
+            .map_or(false, |length| beat >= length)
+        {
+            return vec![];
         }
-        let rounds = 1;
-        let repeat_at = 128 + 64;
-        let restart_at = 128 + 64;
-        let pitch_up = 0;
-        Substructure::Round {
-            notes,
-            rounds,
-            repeat_at,
-            pitch_up,
-            restart_at,
-        }
-    }
-
-    fn get_notes_at(&self, beat: i32) -> Vec<Note> {
         match self {
             Substructure::Round {
                 notes,
@@ -568,22 +471,76 @@ impl Substructure {
                 repeat_at,
                 pitch_up,
                 restart_at,
+                ..
             } => {
+                let cycle_beat = match beat.checked_rem(*restart_at) {
+                    Some(cycle_beat) => cycle_beat,
+                    None => return vec![],
+                };
                 let mut notes_at = Vec::new();
                 for i in 0..*rounds {
-                    let nominal_beat = (beat % restart_at) - (repeat_at * i);
+                    let nominal_beat = cycle_beat - (repeat_at * i);
                     if let Some(note) = notes.get(&nominal_beat) {
                         let note = (note + (*pitch_up * i as usize)) % NOTE_COUNT;
-                        notes_at.push(note);
+                        notes_at.push((i, note));
                     }
                 }
                 notes_at
             }
             Substructure::Scale { notes, interval } => {
-                if beat % interval == 0 && (beat / interval) < (notes.len() as i32) {
-                    vec![*notes
-                        .get((beat / interval) as usize)
-                        .expect("Missing scale note")]
+                if *interval > 0 && beat % interval == 0 && (beat / interval) < (notes.len() as i32)
+                {
+                    vec![(
+                        0,
+                        *notes
+                            .get((beat / interval) as usize)
+                            .expect("Missing scale note"),
+                    )]
+                } else {
+                    vec![]
+                }
+            }
+            Substructure::Chord {
+                notes, restart_at, ..
+            } => beat
+                .checked_rem(*restart_at)
+                .and_then(|beat| notes.get(&beat))
+                .map(|chord| {
+                    chord
+                        .iter()
+                        .enumerate()
+                        .map(|(voice, note)| (voice as i32, *note))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            Substructure::Arpeggio {
+                notes,
+                start,
+                rate,
+                direction,
+                ..
+            } => {
+                let spelled = spell_arpeggio(notes, *direction);
+                let offset = beat - start;
+                if offset >= 0 && *rate > 0 && offset % rate == 0 && !spelled.is_empty() {
+                    vec![(0, spelled[(offset / rate) as usize % spelled.len()])]
+                } else {
+                    vec![]
+                }
+            }
+            Substructure::Ostinato {
+                notes,
+                start,
+                bar_length,
+                ..
+            } => {
+                let bar_length = ostinato_bar_length(*bar_length, subdivision);
+                let offset = beat - start;
+                if offset >= 0 && bar_length > 0 {
+                    notes
+                        .get(&(offset % bar_length))
+                        .map(|note| vec![(0, *note)])
+                        .unwrap_or_default()
                 } else {
                     vec![]
                 }
@@ -591,10 +548,40 @@ impl Substructure {
         }
     }
 
-    fn done(&self, beat: i32) -> bool {
+    /// Sub-beats before the structure starts over.
+    pub fn cycle_length(&self, subdivision: i32) -> i32 {
         match self {
-            Substructure::Round { .. } => false,
-            Substructure::Scale { notes, interval } => beat > (notes.len() as i32 + 1) * interval,
+            Substructure::Round { restart_at, .. } => *restart_at,
+            Substructure::Scale { notes, interval } => notes.len() as i32 * interval,
+            Substructure::Chord { restart_at, .. } => *restart_at,
+            Substructure::Arpeggio {
+                notes,
+                start,
+                rate,
+                direction,
+                loops,
+            } => start + spell_arpeggio(notes, *direction).len() as i32 * rate * loops,
+            Substructure::Ostinato {
+                start,
+                bar_length,
+                bars,
+                ..
+            } => start + ostinato_bar_length(*bar_length, subdivision) * bars,
+        }
+    }
+
+    /// Total sub-beats including loops, or None if the structure loops forever.
+    pub fn length(&self, subdivision: i32) -> Option<i32> {
+        match self {
+            Substructure::Round {
+                restart_at, loops, ..
+            } => loops.map(|loops| loops * restart_at),
+            Substructure::Chord {
+                restart_at, loops, ..
+            } => loops.map(|loops| loops * restart_at),
+            Substructure::Scale { .. }
+            | Substructure::Arpeggio { .. }
+            | Substructure::Ostinato { .. } => Some(self.cycle_length(subdivision)),
         }
     }
 }
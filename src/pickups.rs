@@ -1,4 +1,5 @@
 use crate::prelude::*;
+use crate::score::{Score, ScoreEvent};
 use amethyst::renderer::{palette::Srgba, resources::Tint};
 
 #[derive(Component, Debug, Copy, Clone)]
@@ -27,20 +28,22 @@ impl<'s> System<'s> for NotePickupSystem {
         WriteStorage<'s, NotePickup>,
         WriteStorage<'s, Player>,
         Write<'s, StageState>,
+        Write<'s, Score>,
         Entities<'s>,
         Read<'s, Time>,
         SoundPlayer<'s>,
     );
     fn run(
         &mut self,
-        (mut notes, players, mut stage_state, entities, time, sound): Self::SystemData,
+        (mut notes, players, mut stage_state, mut score, entities, time, sound): Self::SystemData,
     ) {
         for (mut note, note_entity) in (&mut notes, &entities).join() {
             for (player) in (&players).join() {
                 if Some(note.platform) == player.platform && !player.state.is_airborne() {
                     entities.delete(note_entity);
                     stage_state.notes_found.push(note.value);
-                    sound.play_normal(|store| {
+                    score.award(ScoreEvent::Pickup);
+                    sound.play_music(|store| {
                         store
                             .note_scale
                             .get(note.value as usize)
@@ -48,7 +51,7 @@ impl<'s> System<'s> for NotePickupSystem {
                     });
                 }
             }
-            note.ttl -= time.delta_seconds();
+            note.ttl -= time.fixed_seconds();
             if note.ttl < 0.0 {
                 entities.delete(note_entity);
             }
@@ -1,5 +1,8 @@
 use crate::assets::{AnimationId, PrefabStorage, SpriteStorage};
+use crate::autoplay::AutoplaySystem;
 use crate::prelude::*;
+use crate::replay::PlayerInput;
+use crate::tick::Interpolated;
 use amethyst::{
     animation::*,
     assets::Handle,
@@ -7,11 +10,15 @@ use amethyst::{
     ecs::world::LazyBuilder,
     ecs::*,
     error::Error,
-    input::{InputHandler, StringBindings},
     prelude::*,
     renderer::{camera::*, SpriteRender},
 };
 
+/// Jumps per second: a jump takes `1 / JUMP_SPEED` seconds.
+pub const JUMP_SPEED: f32 = 4.0;
+/// Seconds of moving at a platform's edge before the player jumps off it.
+pub const JUMP_WINDUP: f32 = 0.2;
+
 #[derive(Debug, PartialEq)]
 pub enum PlayerState {
     Idle,
@@ -60,11 +67,7 @@ pub struct Player {
     pub on_edge: bool,
 }
 
-fn spawn_player(
-    prefabs: &PrefabStorage,
-    sprites: &SpriteStorage,
-    player_builder: LazyBuilder,
-) -> Entity {
+fn spawn_player(prefabs: &PrefabStorage, player_builder: LazyBuilder) -> Entity {
     let mut transform = Transform::default();
     transform.set_translation_xyz(0., -24., 100.);
     player_builder
@@ -72,11 +75,12 @@ fn spawn_player(
         .with(transform)
         .with(Player {
             move_speed: 64.0,
-            jump_speed: 4.0,
+            jump_speed: JUMP_SPEED,
             state: PlayerState::Waiting { prepped: false },
             platform: None,
             on_edge: false,
         })
+        .with(Interpolated::default())
         .named("player")
         .build()
 }
@@ -86,8 +90,7 @@ pub fn spawn_player_world(world: &mut World) -> Entity {
     let update = world.write_resource::<LazyUpdate>();
     let builder = update.create_entity(&entities);
     let prefabs = world.read_resource::<PrefabStorage>();
-    let sprites = world.read_resource::<SpriteStorage>();
-    let player = spawn_player(&prefabs, &sprites, builder);
+    let player = spawn_player(&prefabs, builder);
     let builder = update.create_entity(&entities);
     player
 }
@@ -163,7 +166,7 @@ impl<'s> System<'s> for PlayerJumpingSystem {
                             lerp(progress, y1, y2) + jump_height(progress),
                             z,
                         );
-                        let new_progress = progress + (time.delta_seconds() * player.jump_speed);
+                        let new_progress = progress + (time.fixed_seconds() * player.jump_speed);
                         if new_progress > 1.0 {
                             player.state = PlayerState::Landing { ttl: 0.1 };
                         } else {
@@ -183,7 +186,7 @@ impl<'s> System<'s> for PlayerJumpingSystem {
                     tx,
                     ty,
                 } => {
-                    if player.on_edge && jump_impulse > 0.2 {
+                    if player.on_edge && jump_impulse > JUMP_WINDUP {
                         if let Some(start) = transforms
                             .get(entity)
                             .map(|transform| transform.translation().clone())
@@ -303,29 +306,29 @@ impl<'s> System<'s> for PlayerWaitingSystem {
         for (mut player) in (&mut players).join() {
             match player.state {
                 PlayerState::Dying { ttd } => {
-                    if ttd < time.delta_seconds() {
+                    if ttd < time.fixed_seconds() {
                         player.state = PlayerState::Respawning { ttl: 0.3 };
                     } else {
                         player.state = PlayerState::Dying {
-                            ttd: ttd - time.delta_seconds(),
+                            ttd: ttd - time.fixed_seconds(),
                         };
                     }
                 }
                 PlayerState::Respawning { ttl } => {
-                    if ttl < time.delta_seconds() {
+                    if ttl < time.fixed_seconds() {
                         player.state = PlayerState::Waiting { prepped: false };
                     } else {
                         player.state = PlayerState::Respawning {
-                            ttl: ttl - time.delta_seconds(),
+                            ttl: ttl - time.fixed_seconds(),
                         };
                     }
                 }
                 PlayerState::Landing { ttl } => {
-                    if ttl < time.delta_seconds() {
+                    if ttl < time.fixed_seconds() {
                         player.state = PlayerState::Idle;
                     } else {
                         player.state = PlayerState::Landing {
-                            ttl: ttl - time.delta_seconds(),
+                            ttl: ttl - time.fixed_seconds(),
                         };
                     }
                 }
@@ -338,7 +341,7 @@ impl<'s> System<'s> for PlayerWaitingSystem {
 struct PlayerMovementSystem;
 impl<'s> System<'s> for PlayerMovementSystem {
     type SystemData = (
-        Read<'s, InputHandler<StringBindings>>,
+        Read<'s, PlayerInput>,
         WriteStorage<'s, Player>,
         WriteStorage<'s, Transform>,
         Read<'s, Time>,
@@ -363,8 +366,8 @@ impl<'s> System<'s> for PlayerMovementSystem {
                     }
                     _ => {
                         let mut translation = transform.translation_mut();
-                        translation.x += x_tilt * player.move_speed * time.delta_seconds();
-                        translation.y += y_tilt * player.move_speed * time.delta_seconds();
+                        translation.x += x_tilt * player.move_speed * time.fixed_seconds();
+                        translation.y += y_tilt * player.move_speed * time.fixed_seconds();
                         if f32::abs(x_tilt) > 0.0 || f32::abs(y_tilt) > 0.0 {
                             let (mut jump_impulse, old_tx, old_ty) = match player.state {
                                 PlayerState::Moving {
@@ -378,7 +381,7 @@ impl<'s> System<'s> for PlayerMovementSystem {
                                 jump_impulse = 0.0;
                             }
                             player.state = PlayerState::Moving {
-                                jump_impulse: jump_impulse + time.delta_seconds(),
+                                jump_impulse: jump_impulse + time.fixed_seconds(),
                                 tx: x_tilt,
                                 ty: y_tilt,
                             };
@@ -402,7 +405,8 @@ impl<'a, 'b> SystemBundle<'a, 'b> for PlayerBundle {
     ) -> Result<(), Error> {
         dispatcher.add(PlayerAnimationSystem, "player_animation", &[]);
         dispatcher.add(PlayerWaitingSystem, "player_waiting", &[]);
-        dispatcher.add(PlayerMovementSystem, "player_movement", &[]);
+        dispatcher.add(AutoplaySystem::default(), "autoplay", &[]);
+        dispatcher.add(PlayerMovementSystem, "player_movement", &["autoplay"]);
         dispatcher.add(
             PlayerPlatformingSystem,
             "player_platforming",
@@ -0,0 +1,70 @@
+pub const MIN_TEMPO: f32 = 0.5;
+pub const MAX_TEMPO: f32 = 1.0;
+
+/// Loops part of a song, optionally slowed down, with misses never ending the run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Practice {
+    /// First sub-beat of the loop.
+    pub loop_start: i32,
+    /// Sub-beat the loop goes back from, or None to play to the end of the song.
+    pub loop_end: Option<i32>,
+    /// Fraction of the song's tempo, from `MIN_TEMPO` to `MAX_TEMPO`.
+    pub tempo: f32,
+}
+
+impl Default for Practice {
+    fn default() -> Self {
+        Practice {
+            loop_start: 0,
+            loop_end: None,
+            tempo: MAX_TEMPO,
+        }
+    }
+}
+
+impl Practice {
+    pub fn new(loop_start: i32, loop_end: Option<i32>, tempo: f32) -> Self {
+        let loop_start = loop_start.max(0);
+        Practice {
+            loop_start,
+            loop_end: loop_end.map(|end| end.max(loop_start + 1)),
+            tempo: tempo.max(MIN_TEMPO).min(MAX_TEMPO),
+        }
+    }
+
+    /// `--practice <start>[-<end>]` sub-beats and `--tempo <fraction>` on the
+    /// command line.
+    pub fn from_args(args: &[String]) -> Result<Option<Self>, String> {
+        let value = |flag: &str| {
+            args.iter()
+                .position(|arg| arg == flag)
+                .and_then(|index| args.get(index + 1))
+        };
+        let range = match value("--practice") {
+            Some(range) => range,
+            None => return Ok(None),
+        };
+        let beat = |beat: &str| {
+            beat.parse::<i32>()
+                .map_err(|err| format!("Invalid practice beat {}: {}", beat, err))
+        };
+        let mut bounds = range.splitn(2, '-');
+        let loop_start = beat(bounds.next().unwrap_or(""))?;
+        let loop_end = match bounds.next() {
+            Some(end) => Some(beat(end)?),
+            None => None,
+        };
+        let tempo = match value("--tempo") {
+            Some(tempo) => tempo
+                .parse::<f32>()
+                .map_err(|err| format!("Invalid tempo {}: {}", tempo, err))?,
+            None => MAX_TEMPO,
+        };
+        Ok(Some(Practice::new(loop_start, loop_end, tempo)))
+    }
+
+    /// Whether a sub-beat's drops and rewards are part of the loop.
+    pub fn contains(&self, beat: i32) -> bool {
+        beat >= self.loop_start && self.loop_end.map_or(true, |end| beat < end)
+    }
+}
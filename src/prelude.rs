@@ -1,8 +1,10 @@
 pub use crate::assets::{
-    AnimationId, PrefabStorage, SoundStorage, SpriteEntityPrefabData, SpriteStorage, NOTE_COUNT,
+    AnimationId, PrefabStorage, SongStorage, SoundStorage, SpriteEntityPrefabData, SpriteStorage,
+    NOTE_COUNT,
 };
-pub use crate::music::Note;
+pub use crate::music::{Note, Song};
 pub use crate::player::Player;
+use crate::settings::Settings;
 pub use crate::stage::{Platform, StageState};
 pub use amethyst::{
     animation::*,
@@ -32,6 +34,7 @@ use amethyst::{
 };
 pub use rand::prelude::*;
 pub use serde::{Deserialize, Serialize};
+use std::sync::{Mutex, MutexGuard};
 
 pub fn get_active_animation<T: amethyst::animation::AnimationSampling>(
     control_set: &AnimationControlSet<AnimationId, T>,
@@ -69,19 +72,70 @@ pub fn set_active_animation<T: amethyst::animation::AnimationSampling>(
     );
 }
 
+/// Pauses every running animation, or restarts the paused ones.
+pub fn pause_animations<T: amethyst::animation::AnimationSampling>(
+    world: &mut World,
+    paused: bool,
+) {
+    world.exec(
+        |mut control_sets: WriteStorage<AnimationControlSet<AnimationId, T>>| {
+            for control_set in (&mut control_sets).join() {
+                let ids = control_set
+                    .animations
+                    .iter()
+                    .filter(|(_, animation)| match animation.state {
+                        ControlState::Paused(_) => !paused,
+                        _ => paused && animation.state.is_running(),
+                    })
+                    .map(|(id, _)| *id)
+                    .collect::<Vec<AnimationId>>();
+                for id in ids {
+                    if paused {
+                        control_set.pause(id);
+                    } else {
+                        control_set.start(id);
+                    }
+                }
+            }
+        },
+    );
+}
+
 #[derive(SystemData)]
 pub struct SoundPlayer<'a> {
     storage: Option<Read<'a, SoundStorage>>,
     output: Option<Read<'a, Output>>,
     sources: Read<'a, AssetStorage<Source>>,
+    settings: Option<Read<'a, Settings>>,
 }
 
+/// Default volume of notes and sound effects.
+pub const NOTE_VOLUME: f32 = 0.75;
+
 impl<'a> SoundPlayer<'a> {
+    /// Plays a sound effect at the effects volume.
     pub fn play_normal(&self, get_sound: impl Fn(&SoundStorage) -> &SourceHandle) {
+        let volume = self
+            .settings
+            .as_ref()
+            .map_or(NOTE_VOLUME, |settings| settings.sfx_gain());
+        self.play(get_sound, volume);
+    }
+
+    /// Plays a note at the music volume.
+    pub fn play_music(&self, get_sound: impl Fn(&SoundStorage) -> &SourceHandle) {
+        let volume = self
+            .settings
+            .as_ref()
+            .map_or(NOTE_VOLUME, |settings| settings.music_gain());
+        self.play(get_sound, volume);
+    }
+
+    fn play(&self, get_sound: impl Fn(&SoundStorage) -> &SourceHandle, volume: f32) {
         if let Some(ref output) = self.output.as_ref() {
             if let Some(ref sounds) = self.storage.as_ref() {
                 if let Some(sound) = self.sources.get(get_sound(&sounds)) {
-                    output.play_once(sound, 0.75);
+                    output.play_once(sound, volume);
                 }
             }
         }
@@ -207,8 +261,21 @@ pub fn lerp(progress: f32, v1: f32, v2: f32) -> f32 {
     v1 + (progress * diff)
 }
 
+lazy_static::lazy_static! {
+    // Gameplay randomness comes from here, so a replay can reseed it.
+    static ref GAME_RNG: Mutex<StdRng> = Mutex::new(StdRng::from_entropy());
+}
+
+pub fn seed_rng(seed: u64) {
+    *game_rng() = StdRng::seed_from_u64(seed);
+}
+
+fn game_rng() -> MutexGuard<'static, StdRng> {
+    GAME_RNG.lock().expect("Game RNG poisoned")
+}
+
 pub fn rand_in<T>(vec: &Vec<T>) -> &T {
-    vec.get(thread_rng().gen_range(0, vec.len()))
+    vec.get(game_rng().gen_range(0, vec.len()))
         .expect("Nothing in vector")
 }
 
@@ -223,7 +290,7 @@ pub fn note_color(note: Note) -> Srgba {
 
 pub fn rand_color() -> Srgba {
     Hsl::new(
-        RgbHue::from_radians(thread_rng().gen_range(0., std::f32::consts::PI * 2.0)),
+        RgbHue::from_radians(game_rng().gen_range(0., std::f32::consts::PI * 2.0)),
         1.,
         0.5,
     )
@@ -235,16 +302,16 @@ pub fn black() -> Srgba {
 }
 
 pub fn rand_upto(max: usize) -> usize {
-    thread_rng().gen_range(0, max)
+    game_rng().gen_range(0, max)
 }
 
 pub fn rand_chance(chance: f32) -> bool {
-    thread_rng().gen_range(0.0, 1.0) < chance
+    game_rng().gen_range(0.0, 1.0) < chance
 }
 
 pub fn rand_shuffle<T>(values: &mut [T]) {
     use rand::seq::SliceRandom;
-    values.shuffle(&mut thread_rng());
+    values.shuffle(&mut *game_rng());
 }
 
 pub const C4: usize = 0;
@@ -0,0 +1,257 @@
+use crate::prelude::*;
+use amethyst::log::{error, warn};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write as _;
+use std::path::PathBuf;
+
+/// Version of the save file format. Bump it when the format changes and teach
+/// `migrate` to read the old version.
+pub const RECORDS_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SongRecord {
+    pub best_score: i32,
+    #[serde(default)]
+    pub fewest_misses: Option<i32>,
+    pub best_notes: usize,
+    pub clears: i32,
+    pub plays: i32,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct GlobalStats {
+    pub plays: i32,
+    pub clears: i32,
+    pub total_score: i64,
+    pub notes_collected: i64,
+    pub misses: i64,
+    pub best_combo: i32,
+}
+
+/// One finished attempt at a song.
+#[derive(Debug, Clone)]
+pub struct SongResult {
+    pub score: i32,
+    pub misses: i32,
+    pub notes: usize,
+    pub best_combo: i32,
+    pub cleared: bool,
+}
+
+/// Records for every song played, by song name, plus totals across all songs.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Records {
+    pub version: u32,
+    pub songs: BTreeMap<String, SongRecord>,
+    pub stats: GlobalStats,
+    /// Whether records are written back to the save file as they change.
+    #[serde(skip)]
+    pub persistent: bool,
+}
+
+impl Default for Records {
+    fn default() -> Self {
+        Records {
+            version: RECORDS_VERSION,
+            songs: BTreeMap::new(),
+            stats: GlobalStats::default(),
+            persistent: false,
+        }
+    }
+}
+
+// Enough of any version of the file to tell which version it is. Format 0 had
+// no version field.
+#[derive(Deserialize)]
+struct VersionProbe {
+    #[serde(default)]
+    version: u32,
+}
+
+// Format 0: per-song records without misses, and no totals.
+#[derive(Deserialize)]
+struct RecordsV0 {
+    songs: BTreeMap<String, SongRecordV0>,
+}
+
+#[derive(Deserialize)]
+struct SongRecordV0 {
+    best_score: i32,
+    best_notes: usize,
+    clears: i32,
+    plays: i32,
+}
+
+impl RecordsV0 {
+    // Totals are worked out from the songs, as far as they go: scores and misses
+    // of past plays weren't kept.
+    fn upgrade(self) -> Records {
+        let mut records = Records::default();
+        for (name, song) in self.songs {
+            records.stats.plays += song.plays;
+            records.stats.clears += song.clears;
+            records.songs.insert(
+                name,
+                SongRecord {
+                    best_score: song.best_score,
+                    fewest_misses: None,
+                    best_notes: song.best_notes,
+                    clears: song.clears,
+                    plays: song.plays,
+                },
+            );
+        }
+        records
+    }
+}
+
+fn from_ron<'a, T: Deserialize<'a>>(text: &'a str) -> Result<T, String> {
+    ron::de::from_str(text).map_err(|err| format!("{}", err))
+}
+
+// Reads a file of an older format, upgrading it one version at a time.
+fn migrate(version: u32, text: &str) -> Result<Records, String> {
+    match version {
+        0 => Ok(from_ron::<RecordsV0>(text)?.upgrade()),
+        RECORDS_VERSION => from_ron(text),
+        version => Err(format!(
+            "saved by a newer version of the game (format {})",
+            version
+        )),
+    }
+}
+
+impl Records {
+    fn path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("ld47").join("records.ron"))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let probe = from_ron::<VersionProbe>(text)?;
+        let mut records = migrate(probe.version, text)?;
+        records.version = RECORDS_VERSION;
+        Ok(records)
+    }
+
+    /// Loads the save file. An unreadable file is moved aside rather than
+    /// overwritten, so a bad migration never loses records. A file from a newer
+    /// version of the game is left as it is, and records aren't saved.
+    pub fn load() -> Self {
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return Records::default(),
+        };
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(_) => {
+                return Records {
+                    persistent: true,
+                    ..Records::default()
+                }
+            }
+        };
+        if let Ok(probe) = from_ron::<VersionProbe>(&text) {
+            if probe.version > RECORDS_VERSION {
+                warn!(
+                    "Not saving records: {} is from a newer version of the game",
+                    path.display()
+                );
+                return Records::default();
+            }
+        }
+        let records = match Self::parse(&text) {
+            Ok(records) => records,
+            Err(err) => {
+                error!("Unreadable records {}: {}", path.display(), err);
+                let backup = path.with_extension("ron.bad");
+                if let Err(err) = fs::rename(&path, &backup) {
+                    error!("Failed to move aside {}: {}", path.display(), err);
+                }
+                Records::default()
+            }
+        };
+        Records {
+            persistent: true,
+            ..records
+        }
+    }
+
+    /// Writes to a temporary file and renames it over the save, so a crash mid
+    /// write leaves the previous save intact.
+    pub fn save(&self) -> Result<(), String> {
+        let path = Self::path().ok_or("No data directory")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| format!("{}: {}", dir.display(), err))?;
+        }
+        let text = ron::ser::to_string_pretty(self, Default::default())
+            .map_err(|err| format!("{}", err))?;
+        let temp = path.with_extension("ron.tmp");
+        let mut file =
+            fs::File::create(&temp).map_err(|err| format!("{}: {}", temp.display(), err))?;
+        file.write_all(text.as_bytes())
+            .and_then(|_| file.sync_all())
+            .map_err(|err| format!("{}: {}", temp.display(), err))?;
+        fs::rename(&temp, &path).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    pub fn song(&self, name: &str) -> Option<&SongRecord> {
+        self.songs.get(name)
+    }
+
+    pub fn record(&mut self, name: &str, result: &SongResult) {
+        let record = self.songs.entry(name.to_string()).or_default();
+        record.plays += 1;
+        record.best_score = record.best_score.max(result.score);
+        record.best_notes = record.best_notes.max(result.notes);
+        if result.cleared {
+            record.clears += 1;
+            record.fewest_misses = Some(
+                record
+                    .fewest_misses
+                    .map_or(result.misses, |misses| misses.min(result.misses)),
+            );
+        }
+
+        let stats = &mut self.stats;
+        stats.plays += 1;
+        if result.cleared {
+            stats.clears += 1;
+        }
+        stats.total_score += result.score as i64;
+        stats.notes_collected += result.notes as i64;
+        stats.misses += result.misses as i64;
+        stats.best_combo = stats.best_combo.max(result.best_combo);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_0_files_are_upgraded() {
+        let text = r#"(
+            songs: {
+                "Loop": (best_score: 1200, best_notes: 8, clears: 2, plays: 5),
+                "Waltz": (best_score: 300, best_notes: 3, clears: 0, plays: 1),
+            },
+        )"#;
+        let records = Records::parse(text).expect("Failed to read format 0 records");
+        assert_eq!(records.version, RECORDS_VERSION);
+        let song = records.song("Loop").expect("Missing song record");
+        assert_eq!(song.best_score, 1200);
+        assert_eq!(song.best_notes, 8);
+        assert_eq!(song.clears, 2);
+        assert_eq!(song.plays, 5);
+        assert_eq!(song.fewest_misses, None);
+        assert_eq!(records.stats.plays, 6);
+        assert_eq!(records.stats.clears, 2);
+    }
+
+    #[test]
+    fn newer_formats_are_not_read() {
+        let text = format!("(version: {}, songs: {{}})", RECORDS_VERSION + 1);
+        assert!(Records::parse(&text).is_err());
+    }
+}
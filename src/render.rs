@@ -0,0 +1,179 @@
+use crate::assets::{InstrumentSource, SCALE};
+use crate::prelude::*;
+use crate::synth::write_samples;
+use amethyst::log::warn;
+use std::path::Path;
+
+pub const RENDER_RATE: u32 = 44100;
+
+/// Mono sample data at its own sample rate.
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub rate: u32,
+    pub data: Vec<f32>,
+}
+
+impl Sample {
+    // Linear interpolation, so samples recorded at any rate mix at the render rate.
+    fn at(&self, time: f32) -> f32 {
+        let position = time * self.rate as f32;
+        let index = position.floor() as usize;
+        match (self.data.get(index), self.data.get(index + 1)) {
+            (Some(a), Some(b)) => lerp(position.fract(), *a, *b),
+            (Some(a), None) => *a,
+            _ => 0.0,
+        }
+    }
+
+    fn duration(&self) -> f32 {
+        self.data.len() as f32 / self.rate as f32
+    }
+}
+
+pub fn load_sample(path: &Path) -> Result<Sample, String> {
+    let mut reader =
+        hound::WavReader::open(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let spec = reader.spec();
+    let samples = match spec.sample_format {
+        hound::SampleFormat::Float => reader
+            .samples::<f32>()
+            .collect::<Result<Vec<f32>, hound::Error>>(),
+        hound::SampleFormat::Int => {
+            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|sample| sample.map(|sample| sample as f32 / scale))
+                .collect::<Result<Vec<f32>, hound::Error>>()
+        }
+    }
+    .map_err(|err| format!("{}: {}", path.display(), err))?;
+    let channels = spec.channels.max(1) as usize;
+    let data = samples
+        .chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+    Ok(Sample {
+        rate: spec.sample_rate,
+        data,
+    })
+}
+
+/// One sample per note in SCALE. Notes without a sample are silent.
+#[derive(Debug, Clone)]
+pub struct Instrument {
+    pub notes: Vec<Option<Sample>>,
+}
+
+/// Loads samples from under `assets`, synthesizing any the instrument doesn't have.
+pub fn load_instrument(assets: &Path, source: &InstrumentSource) -> Instrument {
+    let notes = SCALE
+        .iter()
+        .enumerate()
+        .map(|(note, name)| {
+            let sample = source
+                .sample_path(name)
+                .map(|path| load_sample(&assets.join(path)));
+            match sample {
+                Some(Ok(sample)) => Some(sample),
+                Some(Err(err)) => {
+                    warn!("Missing sample {}", err);
+                    None
+                }
+                None => Some(Sample {
+                    rate: RENDER_RATE,
+                    data: source.patch().synthesize(note, RENDER_RATE),
+                }),
+            }
+        })
+        .collect();
+    Instrument { notes }
+}
+
+fn mix(buffer: &mut Vec<f32>, sample: &Sample, start: f32, rate: u32) {
+    let first = (start * rate as f32).round().max(0.0) as usize;
+    let frames = (sample.duration() * rate as f32).ceil() as usize;
+    if buffer.len() < first + frames {
+        buffer.resize(first + frames, 0.0);
+    }
+    for frame in 0..frames {
+        buffer[first + frame] += sample.at(frame as f32 / rate as f32) * NOTE_VOLUME;
+    }
+}
+
+/// Mixes every note of a song at the time it drops: structures on `drops`,
+/// payouts on `rewards`.
+pub fn render_song(song: &Song, drops: &Instrument, rewards: &Instrument, rate: u32) -> Vec<f32> {
+    let length = song.length().unwrap_or_else(|| song.cycle_length());
+    let mut buffer = vec![0.0; (song.time_at(length as f32) * rate as f32) as usize];
+    for beat in 0..length {
+        let time = song.time_of_beat(beat);
+        for note in song.get_notes_at(beat) {
+            if let Some(Some(sample)) = drops.notes.get(note) {
+                mix(&mut buffer, sample, time, rate);
+            }
+        }
+        for note in song.get_rewards_at(beat, &vec![]) {
+            if let Some(Some(sample)) = rewards.notes.get(note) {
+                mix(&mut buffer, sample, time, rate);
+            }
+        }
+    }
+    buffer
+}
+
+pub fn write_wav(path: &Path, buffer: &[f32], rate: u32) -> Result<(), String> {
+    let file = std::fs::File::create(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    write_samples(std::io::BufWriter::new(file), buffer, rate)
+        .map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::music::Substructure;
+
+    // A power of two, so sample times come out exact.
+    const RATE: u32 = 64;
+
+    fn instrument(note: Note, data: Vec<f32>) -> Instrument {
+        let mut notes = vec![None; SCALE.len()];
+        notes[note] = Some(Sample { rate: RATE, data });
+        Instrument { notes }
+    }
+
+    #[test]
+    fn notes_start_on_their_sub_beats() {
+        // Four sub-beats a second, so sub-beat 4 starts a second in.
+        let song = Song {
+            bpm: 60,
+            structures: vec![Substructure::Scale {
+                notes: vec![3, 3],
+                interval: 4,
+            }],
+            payouts: vec![Substructure::Scale {
+                notes: vec![5],
+                interval: 4,
+            }],
+            ..Song::default()
+        };
+        let drops = instrument(3, vec![1.0, 0.5]);
+        let rewards = instrument(5, vec![0.25]);
+        let buffer = render_song(&song, &drops, &rewards, RATE);
+
+        assert_eq!(buffer.len(), 2 * RATE as usize);
+        let mut expected = vec![0.0; buffer.len()];
+        expected[0] = (1.0 + 0.25) * NOTE_VOLUME;
+        expected[1] = 0.5 * NOTE_VOLUME;
+        expected[RATE as usize] = 1.0 * NOTE_VOLUME;
+        expected[RATE as usize + 1] = 0.5 * NOTE_VOLUME;
+        for (frame, (actual, expected)) in buffer.iter().zip(expected.iter()).enumerate() {
+            assert!(
+                (actual - expected).abs() < 1e-6,
+                "frame {}: {} != {}",
+                frame,
+                actual,
+                expected
+            );
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use crate::clock::{Calibration, SongClock};
+use crate::prelude::*;
+use crate::settings::Settings;
+use amethyst::input::{InputHandler, StringBindings};
+use amethyst::log::{error, info};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+pub const REPLAY_VERSION: u32 = 2;
+
+/// Input axes the player systems read this frame, live or from a replay.
+#[derive(Debug, Clone, Default)]
+pub struct PlayerInput {
+    axes: BTreeMap<String, f32>,
+}
+
+impl PlayerInput {
+    pub fn axis_value(&self, axis: &str) -> Option<f32> {
+        self.axes.get(axis).cloned()
+    }
+
+    pub fn set_axis(&mut self, axis: &str, value: f32) {
+        self.axes.insert(axis.to_string(), value);
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ReplayFrame {
+    pub delta: f32,
+    pub clock: f64,
+    pub axes: BTreeMap<String, f32>,
+}
+
+/// Everything needed to play a session again: the RNG seed, the calibration and
+/// gameplay settings it was played with and each frame's timing and input.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Replay {
+    pub version: u32,
+    pub seed: u64,
+    pub offset: f32,
+    pub lead_time: f32,
+    pub miss_limit: i32,
+    pub frames: Vec<ReplayFrame>,
+}
+
+impl Replay {
+    pub fn load(path: &PathBuf) -> Result<Self, String> {
+        let text =
+            std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        let replay: Replay =
+            ron::de::from_str(&text).map_err(|err| format!("{}: {}", path.display(), err))?;
+        if replay.version != REPLAY_VERSION {
+            return Err(format!(
+                "{}: unsupported replay version {}",
+                path.display(),
+                replay.version
+            ));
+        }
+        Ok(replay)
+    }
+
+    pub fn save(&self, path: &PathBuf) -> Result<(), String> {
+        let text = ron::ser::to_string_pretty(self, Default::default())
+            .map_err(|err| format!("{}", err))?;
+        std::fs::write(path, text).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ReplayMode {
+    Off,
+    Recording { path: PathBuf, replay: Replay },
+    Playing { replay: Replay, frame: usize },
+}
+
+impl Default for ReplayMode {
+    fn default() -> Self {
+        ReplayMode::Off
+    }
+}
+
+impl ReplayMode {
+    /// `--record <file>` or `--replay <file>` on the command line.
+    pub fn from_args(args: &[String], offset: f32, settings: &Settings) -> Self {
+        let value = |flag: &str| {
+            args.iter()
+                .position(|arg| arg == flag)
+                .and_then(|index| args.get(index + 1))
+                .map(PathBuf::from)
+        };
+        if let Some(path) = value("--replay") {
+            match Replay::load(&path) {
+                Ok(replay) => {
+                    info!("Playing replay {}", path.display());
+                    return ReplayMode::Playing { replay, frame: 0 };
+                }
+                Err(err) => error!("Failed to load replay {}", err),
+            }
+        }
+        if let Some(path) = value("--record") {
+            info!("Recording replay to {}", path.display());
+            return ReplayMode::Recording {
+                path,
+                replay: Replay {
+                    version: REPLAY_VERSION,
+                    seed: thread_rng().gen(),
+                    offset,
+                    lead_time: settings.lead_time,
+                    miss_limit: settings.miss_limit,
+                    frames: Vec::new(),
+                },
+            };
+        }
+        ReplayMode::Off
+    }
+
+    /// Seeds the game RNG and sets the calibration, lead time and miss limit for
+    /// the session about to start, once its stage is in place.
+    pub fn start(&self, world: &mut World) {
+        match self {
+            ReplayMode::Off => {}
+            ReplayMode::Recording { replay, .. } | ReplayMode::Playing { replay, .. } => {
+                seed_rng(replay.seed);
+                world.insert(Calibration {
+                    offset: replay.offset,
+                });
+                let mut stage_state = world.write_resource::<StageState>();
+                stage_state.set_lead_time(replay.lead_time);
+                stage_state.set_miss_limit(replay.miss_limit);
+            }
+        }
+    }
+
+    /// Fills in this frame's input, and its timing when playing back. Runs before
+    /// the dispatcher each frame.
+    pub fn begin_frame(&mut self, world: &mut World) {
+        let finished = match self {
+            ReplayMode::Playing { replay, frame } => match replay.frames.get(*frame) {
+                Some(input) => {
+                    *frame += 1;
+                    world
+                        .write_resource::<Time>()
+                        .set_delta_seconds(input.delta);
+                    world
+                        .write_resource::<SongClock>()
+                        .set_fixed(Some(input.clock));
+                    world.write_resource::<PlayerInput>().axes = input.axes.clone();
+                    false
+                }
+                None => true,
+            },
+            _ => false,
+        };
+        if finished {
+            info!("Replay finished");
+            world.write_resource::<SongClock>().set_fixed(None);
+            *self = ReplayMode::Off;
+        }
+        if let ReplayMode::Playing { .. } = self {
+            return;
+        }
+
+        let axes = {
+            let input = world.read_resource::<InputHandler<StringBindings>>();
+            input
+                .bindings
+                .axes()
+                .filter_map(|axis| input.axis_value(axis).map(|value| (axis.clone(), value)))
+                .collect::<BTreeMap<String, f32>>()
+        };
+        if let ReplayMode::Recording { replay, .. } = self {
+            replay.frames.push(ReplayFrame {
+                delta: world.read_resource::<Time>().delta_seconds(),
+                clock: world.read_resource::<SongClock>().now(),
+                axes: axes.clone(),
+            });
+        }
+        world.write_resource::<PlayerInput>().axes = axes;
+    }
+
+    pub fn finish(&self) {
+        if let ReplayMode::Recording { path, replay } = self {
+            match replay.save(path) {
+                Ok(()) => info!("Saved replay of {} frames", replay.frames.len()),
+                Err(err) => error!("Failed to save replay {}", err),
+            }
+        }
+    }
+}
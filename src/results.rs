@@ -0,0 +1,60 @@
+use crate::score::Score;
+
+/// How a run went, from the first song until the player lost.
+#[derive(Debug, Clone, Default)]
+pub struct RunResults {
+    /// The song the run was lost on.
+    pub song: String,
+    /// Seconds of songs survived.
+    pub time: f32,
+    pub misses: i32,
+    pub notes: i32,
+    pub songs_cleared: i32,
+    pub payouts: i32,
+    pub score: i32,
+    pub best_combo: i32,
+}
+
+/// Statistics for the run in progress, and the results of the last one once it
+/// has ended.
+#[derive(Debug, Clone, Default)]
+pub struct RunStats {
+    pub time: f32,
+    pub misses: i32,
+    pub songs_cleared: i32,
+    pub payouts: i32,
+    pub lost_on: String,
+    pub results: Option<RunResults>,
+}
+
+impl RunStats {
+    pub fn end_song(&mut self, misses: i32, cleared: bool) {
+        self.misses += misses;
+        if cleared {
+            self.songs_cleared += 1;
+        }
+    }
+
+    /// Ends the run, keeping its results, and starts counting a new one.
+    pub fn finish(&mut self, score: &Score) {
+        let results = RunResults {
+            song: self.lost_on.clone(),
+            time: self.time,
+            misses: self.misses,
+            notes: score.pickups,
+            songs_cleared: self.songs_cleared,
+            payouts: self.payouts,
+            score: score.points,
+            best_combo: score.best_combo,
+        };
+        *self = RunStats {
+            results: Some(results),
+            ..RunStats::default()
+        };
+    }
+
+    /// The results of a run that has ended, once.
+    pub fn take_results(&mut self) -> Option<RunResults> {
+        self.results.take()
+    }
+}
@@ -0,0 +1,123 @@
+use crate::prelude::*;
+use amethyst::ui::{UiFinder, UiText};
+
+const DODGE_POINTS: i32 = 10;
+const NEAR_MISS_POINTS: i32 = 25;
+const PICKUP_POINTS: i32 = 100;
+const CLEAN_LOOP_POINTS: i32 = 250;
+// Leaving a platform within this many seconds of impact is a near miss.
+pub const NEAR_MISS_WINDOW: f32 = 0.2;
+// Combo needed for each step of the multiplier.
+const COMBO_STEP: i32 = 10;
+const MAX_MULTIPLIER: i32 = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ScoreEvent {
+    Dodge,
+    NearMiss,
+    Pickup,
+    CleanLoop,
+}
+
+impl ScoreEvent {
+    fn points(&self) -> i32 {
+        match self {
+            ScoreEvent::Dodge => DODGE_POINTS,
+            ScoreEvent::NearMiss => NEAR_MISS_POINTS,
+            ScoreEvent::Pickup => PICKUP_POINTS,
+            ScoreEvent::CleanLoop => CLEAN_LOOP_POINTS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Score {
+    pub points: i32,
+    pub combo: i32,
+    pub best_combo: i32,
+    pub dodges: i32,
+    pub near_misses: i32,
+    pub pickups: i32,
+    pub clean_loops: i32,
+    // Points and best combo since the current song started.
+    pub song_points: i32,
+    pub song_best_combo: i32,
+    missed_this_loop: bool,
+}
+
+impl Score {
+    pub fn multiplier(&self) -> i32 {
+        (1 + self.combo / COMBO_STEP).min(MAX_MULTIPLIER)
+    }
+
+    pub fn award(&mut self, event: ScoreEvent) {
+        let points = event.points() * self.multiplier();
+        self.points += points;
+        self.song_points += points;
+        self.combo += 1;
+        self.best_combo = self.best_combo.max(self.combo);
+        self.song_best_combo = self.song_best_combo.max(self.combo);
+        match event {
+            ScoreEvent::Dodge => self.dodges += 1,
+            ScoreEvent::NearMiss => self.near_misses += 1,
+            ScoreEvent::Pickup => self.pickups += 1,
+            ScoreEvent::CleanLoop => self.clean_loops += 1,
+        }
+    }
+
+    pub fn miss(&mut self) {
+        self.combo = 0;
+        self.missed_this_loop = true;
+    }
+
+    /// Called as each loop of the song ends, awarding it if nothing was missed.
+    pub fn end_loop(&mut self) {
+        if !self.missed_this_loop {
+            self.award(ScoreEvent::CleanLoop);
+        }
+        self.missed_this_loop = false;
+    }
+
+    pub fn start_song(&mut self) {
+        self.song_points = 0;
+        self.song_best_combo = self.combo;
+    }
+
+    /// Takes back this song's points so it can be played again from the start.
+    pub fn restart_song(&mut self) {
+        self.points -= self.song_points;
+        self.combo = 0;
+        self.start_song();
+        self.start_loop();
+    }
+
+    /// Starts a loop without judging the one before, e.g. when a song starts.
+    pub fn start_loop(&mut self) {
+        self.missed_this_loop = false;
+    }
+}
+
+/// Shows the score on the HUD from assets/hud.ron.
+pub struct ScoreHudSystem;
+impl<'s> System<'s> for ScoreHudSystem {
+    type SystemData = (Read<'s, Score>, WriteStorage<'s, UiText>, UiFinder<'s>);
+
+    fn run(&mut self, (score, mut texts, finder): Self::SystemData) {
+        if let Some(text) = finder
+            .find("score")
+            .and_then(|entity| texts.get_mut(entity))
+        {
+            text.text = format!("{}", score.points);
+        }
+        if let Some(text) = finder
+            .find("combo")
+            .and_then(|entity| texts.get_mut(entity))
+        {
+            text.text = if score.combo > 0 {
+                format!("{} combo  x{}", score.combo, score.multiplier())
+            } else {
+                String::new()
+            };
+        }
+    }
+}
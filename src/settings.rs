@@ -0,0 +1,110 @@
+use crate::prelude::*;
+use crate::stage::{BALL_DROP_TIME, DEFAULT_MISS_LIMIT};
+use amethyst::log::warn;
+use amethyst::window::Window;
+use amethyst::winit::dpi::LogicalSize;
+use std::path::PathBuf;
+
+pub const WINDOW_SIZES: [(u32, u32); 3] = [(480, 612), (720, 918), (960, 1224)];
+pub const MIN_MISS_LIMIT: i32 = 1;
+pub const MAX_MISS_LIMIT: i32 = 9;
+pub const MIN_LEAD_TIME: f32 = 0.5;
+pub const MAX_LEAD_TIME: f32 = 2.0;
+
+/// Player options, kept in a config file next to the calibration.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Settings {
+    pub master_volume: f32,
+    /// Notes played by the song and by pickups.
+    pub music_volume: f32,
+    /// Jumps, misses and clicks.
+    pub sfx_volume: f32,
+    pub window_size: (u32, u32),
+    pub fullscreen: bool,
+    /// Misses in one song that lose the run.
+    pub miss_limit: i32,
+    /// Seconds between a ball appearing and it landing.
+    pub lead_time: f32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            master_volume: 1.0,
+            music_volume: NOTE_VOLUME,
+            sfx_volume: NOTE_VOLUME,
+            window_size: WINDOW_SIZES[0],
+            fullscreen: false,
+            miss_limit: DEFAULT_MISS_LIMIT,
+            lead_time: BALL_DROP_TIME,
+        }
+    }
+}
+
+impl Settings {
+    fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("ld47").join("settings.ron"))
+    }
+
+    /// The saved settings, or the defaults if there are none.
+    pub fn load() -> Self {
+        let text = match Self::path().and_then(|path| std::fs::read_to_string(path).ok()) {
+            Some(text) => text,
+            None => return Settings::default(),
+        };
+        match ron::de::from_str::<Settings>(&text) {
+            Ok(settings) => settings.clamped(),
+            Err(err) => {
+                warn!("Invalid settings: {}", err);
+                Settings::default()
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = Self::path().ok_or("No config directory")?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|err| format!("{}: {}", dir.display(), err))?;
+        }
+        let text = ron::ser::to_string_pretty(self, Default::default())
+            .map_err(|err| format!("{}", err))?;
+        std::fs::write(&path, text).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    /// The settings with every value brought into its allowed range.
+    pub fn clamped(self) -> Self {
+        let volume = |volume: f32| volume.max(0.0).min(1.0);
+        Settings {
+            master_volume: volume(self.master_volume),
+            music_volume: volume(self.music_volume),
+            sfx_volume: volume(self.sfx_volume),
+            window_size: (self.window_size.0.max(1), self.window_size.1.max(1)),
+            fullscreen: self.fullscreen,
+            miss_limit: self.miss_limit.max(MIN_MISS_LIMIT).min(MAX_MISS_LIMIT),
+            lead_time: self.lead_time.max(MIN_LEAD_TIME).min(MAX_LEAD_TIME),
+        }
+    }
+
+    pub fn music_gain(&self) -> f32 {
+        self.master_volume * self.music_volume
+    }
+
+    pub fn sfx_gain(&self) -> f32 {
+        self.master_volume * self.sfx_volume
+    }
+}
+
+/// Resizes the window and switches fullscreen to match the settings.
+pub fn apply_window(world: &World) {
+    let settings = world.read_resource::<Settings>();
+    if let Some(window) = world.try_fetch::<Window>() {
+        if settings.fullscreen {
+            window.set_fullscreen(Some(window.get_current_monitor()));
+        } else {
+            window.set_fullscreen(None);
+            let (width, height) = settings.window_size;
+            window.set_inner_size(LogicalSize::new(width as f64, height as f64));
+        }
+    }
+}
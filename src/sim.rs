@@ -0,0 +1,233 @@
+use crate::assets::{Instruments, SCALE};
+use crate::autoplay::Autoplay;
+use crate::clock::{Calibration, SongClock};
+use crate::practice::Practice;
+use crate::prelude::*;
+use crate::records::Records;
+use crate::replay::PlayerInput;
+use crate::score::Score;
+use crate::stage::{initialize_stage, StageDescription};
+use crate::tick::FixedStepper;
+use amethyst::{
+    assets::{AssetStorage, Loader},
+    audio::AudioData,
+    core::{transform::Parent, Named},
+    shred::Fetch,
+};
+use std::sync::Arc;
+
+pub const FRAME_SECONDS: f32 = 1.0 / 60.0;
+
+// Handles that never finish loading: there is no processor in a simulation.
+fn stub_sounds(world: &World) -> SoundStorage {
+    let loader = world.read_resource::<Loader>();
+    let sources = world.read_resource::<AssetStorage<Source>>();
+    let sound = || loader.load_from_data(AudioData(Vec::new()), (), &sources);
+    SoundStorage {
+        jump: sound(),
+        miss: sound(),
+        tap: sound(),
+        foo_scale: SCALE.iter().map(|_| sound()).collect(),
+        note_scale: SCALE.iter().map(|_| sound()).collect(),
+        instruments: Instruments::default(),
+    }
+}
+
+fn stub_prefabs(world: &World) -> PrefabStorage {
+    let loader = world.read_resource::<Loader>();
+    let prefabs = world.read_resource::<AssetStorage<Prefab<SpriteEntityPrefabData>>>();
+    let prefab = || loader.load_from_data(Prefab::new(), (), &prefabs);
+    PrefabStorage {
+        player: prefab(),
+        notes: prefab(),
+        ball: prefab(),
+        shadow: prefab(),
+        platform: prefab(),
+        backdrop: prefab(),
+    }
+}
+
+/// The stage, player and hazard systems in a world with no window, renderer or
+/// audio device, stepped on fake time with scripted input.
+pub struct Simulation {
+    world: World,
+    stepper: FixedStepper,
+    time: f64,
+}
+
+impl Simulation {
+    pub fn new(stage_desc: StageDescription, songs: Vec<Song>) -> Self {
+        let mut world = World::new();
+        world.register::<Transform>();
+        world.register::<Parent>();
+        world.register::<Named>();
+        world.register::<SpriteRender>();
+        world.register::<Tint>();
+        world.register::<Handle<Prefab<SpriteEntityPrefabData>>>();
+
+        let mut clock = SongClock::default();
+        clock.set_fixed(Some(0.0));
+        world.insert(clock);
+        world.insert(Time::default());
+        world.insert(Calibration::default());
+        world.insert(Records::default());
+        world.insert(PlayerInput::default());
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .expect("Failed to build simulation thread pool");
+        world.insert(Loader::new(".", Arc::new(pool)));
+        world.insert(AssetStorage::<Source>::default());
+        world.insert(AssetStorage::<Prefab<SpriteEntityPrefabData>>::default());
+        let sounds = stub_sounds(&world);
+        let prefabs = stub_prefabs(&world);
+        world.insert(sounds);
+        world.insert(prefabs);
+
+        initialize_stage(&mut world, stage_desc, songs);
+        let stepper = FixedStepper::new(&mut world).expect("Failed to build gameplay systems");
+        world.maintain();
+        Simulation {
+            world,
+            stepper,
+            time: 0.0,
+        }
+    }
+
+    pub fn world(&mut self) -> &mut World {
+        &mut self.world
+    }
+
+    pub fn stage(&self) -> Fetch<StageState> {
+        self.world.read_resource::<StageState>()
+    }
+
+    pub fn score(&self) -> Fetch<Score> {
+        self.world.read_resource::<Score>()
+    }
+
+    pub fn records(&self) -> Fetch<Records> {
+        self.world.read_resource::<Records>()
+    }
+
+    /// Seconds of fake time simulated so far.
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// Holds the movement axes, as the keyboard would, until changed.
+    pub fn set_input(&mut self, leftright: f32, updown: f32) {
+        let mut input = self.world.write_resource::<PlayerInput>();
+        input.set_axis("leftright", leftright);
+        input.set_axis("updown", updown);
+    }
+
+    /// Hands the controls to the autoplay bot, or takes them back.
+    pub fn set_autoplay(&mut self, enabled: bool) {
+        self.world.insert(Autoplay { enabled });
+    }
+
+    /// Loops part of the songs started from now on.
+    pub fn set_practice(&mut self, practice: Option<Practice>) {
+        self.world
+            .write_resource::<StageState>()
+            .set_practice(practice);
+    }
+
+    /// Runs one frame of `seconds`.
+    pub fn step(&mut self, seconds: f32) {
+        self.time += seconds as f64;
+        self.world
+            .write_resource::<SongClock>()
+            .set_fixed(Some(self.time));
+        self.world
+            .write_resource::<Time>()
+            .set_delta_seconds(seconds);
+        self.stepper.step(&mut self.world);
+    }
+
+    pub fn run_for(&mut self, seconds: f32) {
+        let end = self.time + seconds as f64;
+        while self.time < end {
+            self.step(FRAME_SECONDS);
+        }
+    }
+
+    /// Runs frames until `done` holds, giving up after `max_seconds`. Returns
+    /// whether `done` was reached.
+    pub fn run_until(&mut self, max_seconds: f32, done: impl Fn(&Simulation) -> bool) -> bool {
+        let end = self.time + max_seconds as f64;
+        while !done(self) {
+            if self.time >= end {
+                return false;
+            }
+            self.step(FRAME_SECONDS);
+        }
+        true
+    }
+
+    pub fn run_until_beat(&mut self, beat: i32, max_seconds: f32) -> bool {
+        self.run_until(max_seconds, |sim| {
+            let stage = sim.stage();
+            stage.playing && stage.beat() >= beat
+        })
+    }
+
+    /// Jumps onto the spawn platform like a player starting a game, so the first
+    /// song begins.
+    pub fn start_song(&mut self, max_seconds: f32) -> bool {
+        self.set_input(0.0, 1.0);
+        let started = self.run_until(max_seconds, |sim| sim.stage().playing);
+        self.set_input(0.0, 0.0);
+        started
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::music::Substructure;
+    use crate::stage::note_at;
+
+    // One drop every beat at 60 bpm, so sub-beat 4 lands one second after sub-beat 0.
+    fn drops_on(notes: Vec<Note>) -> Song {
+        Song {
+            name: "Test".to_string(),
+            bpm: 60,
+            structures: vec![Substructure::Scale { notes, interval: 4 }],
+            ..Song::default()
+        }
+    }
+
+    fn play(song: Song) -> Simulation {
+        let mut sim = Simulation::new(StageDescription::default(), vec![song]);
+        assert!(sim.start_song(10.0), "the song never started");
+        // Both balls have landed and the loop has ended, but the song is still on.
+        assert!(
+            sim.run_until_beat(10, 20.0),
+            "the song never reached beat 10"
+        );
+        sim
+    }
+
+    #[test]
+    fn balls_landing_on_the_player_are_missed() {
+        let spawn = note_at(2, 2);
+        let sim = play(drops_on(vec![spawn, spawn]));
+        assert_eq!(sim.stage().missed(), 2);
+        let score = sim.score();
+        assert_eq!(score.combo, 0);
+        assert_eq!(score.clean_loops, 0);
+        assert_eq!(score.points, 0);
+    }
+
+    #[test]
+    fn a_loop_without_misses_scores_a_clean_loop() {
+        let corner = note_at(0, 0);
+        let sim = play(drops_on(vec![corner, corner]));
+        assert_eq!(sim.stage().missed(), 0);
+        let score = sim.score();
+        assert_eq!(score.clean_loops, 1);
+        assert_eq!(score.points, 250);
+    }
+}
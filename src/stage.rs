@@ -1,15 +1,24 @@
+use crate::clock::Calibration;
 use crate::music::*;
 use crate::pickups::*;
 use crate::player::*;
+use crate::practice::Practice;
 use crate::prelude::*;
+use crate::records::{Records, SongResult};
+use crate::results::RunStats;
+use crate::score::*;
+use crate::settings::Settings;
+use crate::tick::{FixedTick, Interpolated};
+use crate::validate::*;
 use amethyst::{
     animation::*,
-    assets::Handle,
+    assets::{AssetStorage, Handle},
     core::{bundle::SystemBundle, transform::*},
     ecs::world::LazyBuilder,
     ecs::*,
     error::Error,
     input::{InputHandler, StringBindings},
+    log::error,
     prelude::*,
     renderer::{camera::*, SpriteRender},
     window::ScreenDimensions,
@@ -19,6 +28,19 @@ use std::collections::HashMap;
 const TILE_SIZE: u32 = 32;
 const TILE_CENTER: (u32, u32) = (0, 8);
 const FLOOR_TILE: usize = 0;
+pub const NOTES_TO_WIN: usize = 8;
+/// Default seconds between a ball appearing and it landing.
+pub const BALL_DROP_TIME: f32 = 1.0;
+// Height balls fall from.
+const DROP_HEIGHT: f32 = 100.0;
+/// Seconds a note pickup stays on its platform.
+pub const PICKUP_TIME: f32 = 5.0;
+/// Default misses that lose a song.
+pub const DEFAULT_MISS_LIMIT: i32 = 3;
+// Miss pips on the HUD, laid out in rows from the backdrop's first three.
+const MISS_PIPS_PER_ROW: i32 = 3;
+const MISS_PIP_SPACING: (f32, f32) = (64.0, 32.0);
+const FIRST_MISS_PIP: (f32, f32) = (0.0, 80.0);
 const STAGE_SIZE: (f32, f32) = (
     TILE_SIZE as f32 * 5., // 160
     100. + // Dropsize
@@ -59,21 +81,42 @@ pub struct Platform {
 pub struct Ball {
     ttl: f32,
     ttd: f32,
+    // Time until the note sounds, ahead of landing by the calibrated offset.
+    tts: f32,
     drop_speed: f32,
     hit: bool,
+    sounded: bool,
+    // Whether the player stood under the ball while it fell, and the time to
+    // impact when they last stepped off.
+    threatened: bool,
+    cleared_at: Option<f32>,
     platform: Entity,
 }
 
 impl Ball {
-    fn new(platform: Entity) -> Self {
+    fn new(platform: Entity, offset: f32, lead_time: f32) -> Self {
+        let tts = lead_time - offset;
         Ball {
-            ttl: 1.2,
-            ttd: 1.0,
-            drop_speed: 100.0,
+            ttl: lead_time.max(tts) + 0.2,
+            ttd: lead_time,
+            tts,
+            drop_speed: DROP_HEIGHT / lead_time,
             hit: false,
+            sounded: false,
+            threatened: false,
+            cleared_at: None,
             platform,
         }
     }
+
+    /// Runs the fall forward by `elapsed` seconds, returning the distance fallen.
+    fn advance(&mut self, elapsed: f32) -> f32 {
+        let fallen = self.drop_speed * elapsed.min(self.ttd.max(0.0));
+        self.ttd -= elapsed;
+        self.tts -= elapsed;
+        self.ttl -= elapsed;
+        fallen
+    }
 }
 
 #[derive(Component, Debug, Copy, Clone)]
@@ -99,35 +142,46 @@ pub struct StageDescription {
 pub struct StageState {
     platforms: HashMap<(u32, u32), Entity>,
     time_in_song: f32,
+    // SongClock time of the song's start, None until the next beat update.
+    clock_origin: Option<f64>,
+    drop_offset: f32,
+    lead_time: f32,
+    miss_limit: i32,
     missed: i32,
     pub notes_found: Vec<Note>,
     pub winning: bool,
     pub losing: bool,
     pub playing: bool,
     song: Song,
-    songs: [Song; SONG_COUNT],
+    songs: Vec<Song>,
     song_index: i32,
+    practice: Option<Practice>,
 }
 
 impl Default for StageState {
     fn default() -> Self {
-        StageState::new(HashMap::new(), Song::alouette())
+        StageState::new(HashMap::new(), Vec::new())
     }
 }
 
 impl StageState {
-    pub fn new(platforms: HashMap<(u32, u32), Entity>, song: Song) -> Self {
+    pub fn new(platforms: HashMap<(u32, u32), Entity>, songs: Vec<Song>) -> Self {
         StageState {
             platforms,
             time_in_song: -4.0,
+            clock_origin: None,
+            drop_offset: 0.0,
+            lead_time: BALL_DROP_TIME,
+            miss_limit: DEFAULT_MISS_LIMIT,
             missed: 0,
             notes_found: Vec::new(),
             winning: false,
             losing: false,
             playing: false,
-            song,
-            songs: Song::songs(),
+            song: Song::default(),
+            songs,
             song_index: 0,
+            practice: None,
         }
     }
     pub fn get_spawn(&self) -> Option<&Entity> {
@@ -159,7 +213,7 @@ impl StageState {
 
     pub fn win(&mut self) {
         self.song = Song::payout_song(&self.notes_found);
-        self.time_in_song = -0.5;
+        self.set_time(-0.5);
         self.missed = 0;
         self.notes_found = Vec::new();
         self.winning = true;
@@ -178,14 +232,24 @@ impl StageState {
 
     pub fn lose(&mut self) {
         self.song = Song::lose_song();
-        self.time_in_song = -0.5;
-        self.missed = 3;
+        self.set_time(-0.5);
+        self.missed = self.miss_limit;
         self.losing = true;
     }
 
     pub fn start_new_song(&mut self) {
-        self.song = self.songs[(self.song_index as usize) % SONG_COUNT].clone();
-        self.time_in_song = -4.0;
+        if let Some(song) = self
+            .songs
+            .get((self.song_index as usize) % self.songs.len().max(1))
+        {
+            self.song = song.clone();
+        }
+        self.set_time(-4.0);
+        if let Some(practice) = self.practice.clone() {
+            self.song = self.song.with_tempo(practice.tempo);
+            let start = self.song.time_of_beat(practice.loop_start);
+            self.set_drop_time(start - 4.0);
+        }
         self.playing = true;
         self.winning = false;
         self.losing = false;
@@ -193,8 +257,135 @@ impl StageState {
         self.notes_found = Vec::new();
     }
 
-    fn beat(&self) -> i32 {
-        (SUBNOTES as f32 * self.time_in_song * ((self.song.bpm as f32) / 60.0)) as i32
+    pub fn next_song(&mut self) {
+        self.song_index += 1;
+        self.start_new_song();
+    }
+
+    fn set_time(&mut self, time: f32) {
+        self.time_in_song = time;
+        self.clock_origin = None;
+    }
+
+    fn set_drop_time(&mut self, time: f32) {
+        self.set_time(time + self.drop_offset);
+    }
+
+    /// Loops the next song started, or plays songs normally again with None.
+    pub fn set_practice(&mut self, practice: Option<Practice>) {
+        self.practice = practice;
+    }
+
+    pub fn practice(&self) -> Option<&Practice> {
+        self.practice.as_ref()
+    }
+
+    // Whether a sub-beat of the current song drops balls and rewards.
+    fn plays_beat(&self, beat: i32) -> bool {
+        match &self.practice {
+            Some(practice) if !self.winning && !self.losing => practice.contains(beat),
+            _ => true,
+        }
+    }
+
+    // Sub-beat the practice loop goes back from, if one is playing.
+    fn loop_end(&self) -> Option<i32> {
+        match &self.practice {
+            Some(practice) if !self.winning && !self.losing => {
+                practice.loop_end.or_else(|| self.song.length())
+            }
+            _ => None,
+        }
+    }
+
+    // Goes back to the start of the practice loop once the drop clock reaches its
+    // end. Balls already falling still land in the gap before the loop restarts.
+    fn loop_practice(&mut self, beat: i32) -> bool {
+        match (self.loop_end(), self.practice.clone()) {
+            (Some(end), Some(practice)) if beat >= end => {
+                let start = self.song.time_of_beat(practice.loop_start);
+                self.set_drop_time(start - self.lead_time);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Moves the song position to the clock's, anchoring the clock on the first call
+    /// after the position was set.
+    pub fn sync_to_clock(&mut self, now: f64, drop_offset: f32) {
+        self.drop_offset = drop_offset;
+        let origin = *self
+            .clock_origin
+            .get_or_insert(now - self.time_in_song as f64);
+        self.time_in_song = (now - origin) as f32;
+    }
+
+    pub fn song_name(&self) -> &str {
+        &self.song.name
+    }
+
+    pub fn lead_time(&self) -> f32 {
+        self.lead_time
+    }
+
+    pub fn set_lead_time(&mut self, lead_time: f32) {
+        self.lead_time = lead_time;
+    }
+
+    /// Misses that lose a song.
+    pub fn miss_limit(&self) -> i32 {
+        self.miss_limit
+    }
+
+    pub fn set_miss_limit(&mut self, miss_limit: i32) {
+        self.miss_limit = miss_limit;
+    }
+
+    pub fn song(&self) -> &Song {
+        &self.song
+    }
+
+    /// Song position drops are scheduled on, behind the song clock by the
+    /// calibrated offset.
+    pub fn drop_time(&self) -> f32 {
+        self.time_in_song - self.drop_offset
+    }
+
+    /// Seconds until the next whole beat of the song, and the length of a beat.
+    pub fn beat_timing(&self) -> (f32, f32) {
+        let subdivision = self.song.subdivision.max(1);
+        let sub_beat = self.song.sub_beat_at(self.time_in_song);
+        let next_beat = ((sub_beat / subdivision as f32).floor() + 1.0) * subdivision as f32;
+        let beat_length = 60.0 / self.song.bpm_at(sub_beat).max(1.0);
+        (
+            self.song.time_at(next_beat) - self.time_in_song,
+            beat_length,
+        )
+    }
+
+    pub fn beat(&self) -> i32 {
+        self.song.beat_at(self.time_in_song)
+    }
+
+    pub fn missed(&self) -> i32 {
+        self.missed
+    }
+
+    pub fn platform_at(&self, x: u32, y: u32) -> Option<&Entity> {
+        self.platforms.get(&(x, y))
+    }
+
+    pub fn progress(&self) -> f32 {
+        self.song.progress(self.beat())
+    }
+
+    /// True once every note has dropped and the last ball has landed.
+    pub fn song_finished(&self) -> bool {
+        self.song.finished(
+            self.song
+                .beat_at(self.time_in_song - self.lead_time - self.drop_offset),
+        )
     }
 }
 
@@ -208,7 +399,52 @@ impl Default for StageDescription {
     }
 }
 
-fn note_at(x: u32, y: u32) -> Note {
+impl StageDescription {
+    /// Layouts players can pick from, by name. Each contains the spawn platform.
+    pub fn layouts() -> Vec<(&'static str, StageDescription)> {
+        vec![
+            ("Full", StageDescription::default()),
+            (
+                "Square",
+                StageDescription {
+                    width: 4,
+                    height: 4,
+                    player_spawn: (0, 0),
+                },
+            ),
+            (
+                "Compact",
+                StageDescription {
+                    width: 3,
+                    height: 3,
+                    player_spawn: (0, 0),
+                },
+            ),
+        ]
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn has_platform(&self, note: Note) -> bool {
+        ((note % 5) as u32) < self.width && ((note / 5) as u32) < self.height
+    }
+
+    /// Notes of the platforms a player can jump to from the platform playing `note`.
+    pub fn neighbours(&self, note: Note) -> Vec<Note> {
+        let (x, y) = ((note % 5) as i32, (note / 5) as i32);
+        [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)]
+            .iter()
+            .filter(|(x, y)| {
+                *x >= 0 && *y >= 0 && (*x as u32) < self.width && (*y as u32) < self.height
+            })
+            .map(|(x, y)| note_at(*x as u32, *y as u32))
+            .collect()
+    }
+}
+
+pub fn note_at(x: u32, y: u32) -> Note {
     (x + y * 5) as Note
 }
 
@@ -253,7 +489,23 @@ fn spawn_chute(world: &mut World, x: u32, y: u32) {
     });
 }
 
-pub fn initialize_stage(world: &mut World, stage_desc: StageDescription) {
+/// Songs from the loaded song library that are playable on a stage.
+pub fn library_songs(world: &World, stage_desc: &StageDescription) -> Vec<Song> {
+    let library = world.read_resource::<SongStorage>();
+    let song_assets = world.read_resource::<AssetStorage<Song>>();
+    library
+        .songs
+        .iter()
+        .filter_map(|handle| song_assets.get(handle).cloned())
+        .filter(|song| {
+            let diagnostics = validate_song(song, stage_desc);
+            log_diagnostics(&diagnostics);
+            !has_errors(&diagnostics)
+        })
+        .collect()
+}
+
+pub fn initialize_stage(world: &mut World, stage_desc: StageDescription, songs: Vec<Song>) {
     let mut platforms = HashMap::new();
     if let Some((player_spawn, translation)) = {
         world.exec(|spawner: PrefabSpawner| {
@@ -304,7 +556,45 @@ pub fn initialize_stage(world: &mut World, stage_desc: StageDescription) {
         spawn_player_world(world);
     }
     world.insert::<StageDescription>(stage_desc);
-    world.insert::<StageState>(StageState::new(platforms, Song::alouette()));
+    let mut stage_state = StageState::new(platforms, songs);
+    if let Some(settings) = world.try_fetch::<Settings>() {
+        stage_state.set_lead_time(settings.lead_time);
+        stage_state.set_miss_limit(settings.miss_limit);
+    }
+    world.insert::<StageState>(stage_state);
+    world.insert(Score::default());
+    world.insert(RunStats::default());
+}
+
+/// Starts the current song again, clearing everything dropped so far and the
+/// points it has earned. Does nothing outside of a song.
+pub fn restart_song(world: &mut World) {
+    {
+        let stage_state = world.read_resource::<StageState>();
+        if !stage_state.playing || stage_state.winning || stage_state.losing {
+            return;
+        }
+    }
+    world.exec(
+        |(entities, balls, shadows, pickups): (
+            Entities,
+            ReadStorage<Ball>,
+            ReadStorage<Shadow>,
+            ReadStorage<NotePickup>,
+        )| {
+            for (entity, _) in (&entities, &balls).join() {
+                entities.delete(entity);
+            }
+            for (entity, _) in (&entities, &shadows).join() {
+                entities.delete(entity);
+            }
+            for (entity, _) in (&entities, &pickups).join() {
+                entities.delete(entity);
+            }
+        },
+    );
+    world.write_resource::<StageState>().start_new_song();
+    world.write_resource::<Score>().restart_song();
 }
 
 struct PlatformAnimationSystem;
@@ -319,6 +609,7 @@ impl<'s> System<'s> for PlatformAnimationSystem {
         ReadStorage<'s, AnimationSet<AnimationId, Transform>>,
         WriteStorage<'s, AnimationControlSet<AnimationId, Transform>>,
         Write<'s, StageState>,
+        Write<'s, Score>,
         Entities<'s>,
         SoundPlayer<'s>,
     );
@@ -335,14 +626,16 @@ impl<'s> System<'s> for PlatformAnimationSystem {
             t_animation_sets,
             mut t_control_sets,
             mut stage_state,
+            mut score,
             entities,
             sound,
         ): Self::SystemData,
     ) {
+        // Animation sets are optional so platforms still play without loaded prefabs.
         for (platform, animation_set, t_animation_set, entity) in (
             &mut platforms,
-            &animation_sets,
-            &t_animation_sets,
+            animation_sets.maybe(),
+            t_animation_sets.maybe(),
             &entities,
         )
             .join()
@@ -360,15 +653,49 @@ impl<'s> System<'s> for PlatformAnimationSystem {
                 }
             }
             let mut need_to_play = false;
+            let mut impact = false;
+            let mut dodge = None;
             for (mut ball) in (&mut balls).join() {
-                if ball.platform == entity && !ball.hit && ball.ttd <= 0.0 {
+                if ball.platform != entity {
+                    continue;
+                }
+                if !ball.hit && platform.has_player {
+                    ball.threatened = true;
+                    ball.cleared_at = None;
+                } else if !ball.hit && ball.threatened && ball.cleared_at.is_none() {
+                    ball.cleared_at = Some(ball.ttd);
+                }
+                if !ball.sounded && ball.tts <= 0.0 {
                     need_to_play = true;
+                    ball.sounded = true;
+                }
+                if !ball.hit && ball.ttd <= 0.0 {
+                    impact = true;
                     ball.hit = true;
+                    if let Some(cleared_at) = ball.cleared_at {
+                        dodge = Some(if cleared_at <= NEAR_MISS_WINDOW {
+                            ScoreEvent::NearMiss
+                        } else {
+                            ScoreEvent::Dodge
+                        });
+                    }
+                }
+            }
+            if let Some(event) = dodge {
+                if stage_state.playing && !stage_state.winning && !stage_state.losing {
+                    score.award(event);
                 }
             }
             if !stage_state.losing && platform.dead {
                 platform.dead = false;
-                if let (Some(control_set), Some(t_control_set)) = (
+                if let (
+                    Some(animation_set),
+                    Some(t_animation_set),
+                    Some(control_set),
+                    Some(t_control_set),
+                ) = (
+                    animation_set,
+                    t_animation_set,
                     get_animation_set(&mut control_sets, entity),
                     get_animation_set(&mut t_control_sets, entity),
                 ) {
@@ -388,77 +715,99 @@ impl<'s> System<'s> for PlatformAnimationSystem {
                     );
                 }
             }
-            if need_to_wobble || need_to_play {
-                if need_to_wobble {
-                    sound.play_normal(|store| &store.tap);
-                } else if platform.has_player && !stage_state.winning && !stage_state.losing {
-                    sound.play_normal(|store| &store.miss);
-                    stage_state.missed += 1;
-                } else {
-                    if stage_state.losing {
-                        platform.dead = true;
-                        if let (Some(control_set), Some(t_control_set)) = (
-                            get_animation_set(&mut control_sets, entity),
-                            get_animation_set(&mut t_control_sets, entity),
-                        ) {
-                            set_active_animation(
-                                control_set,
-                                AnimationId::Die,
-                                &animation_set,
-                                EndControl::Loop(None),
-                                1.0,
-                            );
-                            set_active_animation(
-                                t_control_set,
-                                AnimationId::Die,
-                                &t_animation_set,
-                                EndControl::Stay,
-                                1.0,
-                            );
-                        }
-                    }
-                    if !stage_state.winning {
-                        sound.play_normal(|store| {
-                            store
-                                .foo_scale
-                                .get(platform.note as usize)
-                                .expect("Missing note")
-                        });
-                    } else {
-                        sound.play_normal(|store| {
-                            store
-                                .note_scale
-                                .get(platform.note as usize)
-                                .expect("Missing note")
-                        });
-                    }
+            if need_to_wobble {
+                sound.play_normal(|store| &store.tap);
+            } else if platform.has_player && !stage_state.winning && !stage_state.losing {
+                if impact {
+                    miss(&mut stage_state, &mut score, &sound);
                 }
-                if !stage_state.losing {
-                    if let (Some(control_set), Some(t_control_set)) = (
+            } else {
+                if impact && stage_state.losing {
+                    platform.dead = true;
+                    if let (
+                        Some(animation_set),
+                        Some(t_animation_set),
+                        Some(control_set),
+                        Some(t_control_set),
+                    ) = (
+                        animation_set,
+                        t_animation_set,
                         get_animation_set(&mut control_sets, entity),
                         get_animation_set(&mut t_control_sets, entity),
                     ) {
                         set_active_animation(
                             control_set,
-                            AnimationId::Move,
+                            AnimationId::Die,
                             &animation_set,
-                            EndControl::Stay,
+                            EndControl::Loop(None),
                             1.0,
                         );
                         set_active_animation(
                             t_control_set,
-                            AnimationId::Move,
+                            AnimationId::Die,
                             &t_animation_set,
                             EndControl::Stay,
                             1.0,
                         );
                     }
                 }
+                if need_to_play {
+                    if !stage_state.winning {
+                        sound.play_music(|store| {
+                            store
+                                .foo_scale
+                                .get(platform.note as usize)
+                                .expect("Missing note")
+                        });
+                    } else {
+                        sound.play_music(|store| {
+                            store
+                                .note_scale
+                                .get(platform.note as usize)
+                                .expect("Missing note")
+                        });
+                    }
+                }
+            }
+            if (need_to_wobble || impact) && !stage_state.losing {
+                if let (
+                    Some(animation_set),
+                    Some(t_animation_set),
+                    Some(control_set),
+                    Some(t_control_set),
+                ) = (
+                    animation_set,
+                    t_animation_set,
+                    get_animation_set(&mut control_sets, entity),
+                    get_animation_set(&mut t_control_sets, entity),
+                ) {
+                    set_active_animation(
+                        control_set,
+                        AnimationId::Move,
+                        &animation_set,
+                        EndControl::Stay,
+                        1.0,
+                    );
+                    set_active_animation(
+                        t_control_set,
+                        AnimationId::Move,
+                        &t_animation_set,
+                        EndControl::Stay,
+                        1.0,
+                    );
+                }
             }
         }
     }
 }
 
+// A ball landed on the player.
+fn miss(stage_state: &mut StageState, score: &mut Score, sound: &SoundPlayer) {
+    sound.play_normal(|store| &store.miss);
+    stage_state.missed += 1;
+    score.miss();
+}
+
 struct BallDropperSystem;
 impl<'s> System<'s> for BallDropperSystem {
     type SystemData = (
@@ -486,10 +835,17 @@ impl<'s> System<'s> for BallDropperSystem {
             time,
         ): Self::SystemData,
     ) {
-        for (mut ball, animation_set, mut transform, entity) in
-            (&mut balls, &animation_sets, &mut transforms, &entities).join()
+        for (mut ball, animation_set, mut transform, entity) in (
+            &mut balls,
+            animation_sets.maybe(),
+            &mut transforms,
+            &entities,
+        )
+            .join()
         {
-            if let Some(control_set) = get_animation_set(&mut control_sets, entity) {
+            if let (Some(animation_set), Some(control_set)) =
+                (animation_set, get_animation_set(&mut control_sets, entity))
+            {
                 if get_active_animation(control_set).is_none() {
                     set_active_animation(
                         control_set,
@@ -501,17 +857,21 @@ impl<'s> System<'s> for BallDropperSystem {
                 }
             }
             if ball.ttd > 0.0 {
-                transform.translation_mut().y -= ball.drop_speed * time.delta_seconds();
+                transform.translation_mut().y -= ball.drop_speed * time.fixed_seconds();
             }
-            ball.ttd -= time.delta_seconds();
-            ball.ttl -= time.delta_seconds();
+            ball.ttd -= time.fixed_seconds();
+            ball.tts -= time.fixed_seconds();
+            ball.ttl -= time.fixed_seconds();
             if ball.ttl < 0.0 {
                 entities.delete(entity);
             }
         }
-        for (mut shadow, animation_set, entity) in (&mut shadows, &animation_sets, &entities).join()
+        for (mut shadow, animation_set, entity) in
+            (&mut shadows, animation_sets.maybe(), &entities).join()
         {
-            if let Some(control_set) = get_animation_set(&mut control_sets, entity) {
+            if let (Some(animation_set), Some(control_set)) =
+                (animation_set, get_animation_set(&mut control_sets, entity))
+            {
                 if get_active_animation(control_set).is_none() {
                     set_active_animation(
                         control_set,
@@ -522,7 +882,7 @@ impl<'s> System<'s> for BallDropperSystem {
                     );
                 }
             }
-            shadow.ttl -= time.delta_seconds();
+            shadow.ttl -= time.fixed_seconds();
             if shadow.ttl < 0.0 {
                 entities.delete(entity);
             }
@@ -538,28 +898,68 @@ impl<'s> System<'s> for PlatformBeatSystem {
         ReadStorage<'s, Transform>,
         Read<'s, StageDescription>,
         Write<'s, StageState>,
-        Read<'s, Time>,
+        Write<'s, Score>,
+        Read<'s, FixedTick>,
+        Read<'s, Calibration>,
         PrefabSpawner<'s>,
         SoundPlayer<'s>,
     );
 
     fn run(
         &mut self,
-        (mut platforms, parents, transforms, stage_desc, mut stage_state, time, spawner, sound): Self::SystemData,
+        (
+            mut platforms,
+            parents,
+            transforms,
+            stage_desc,
+            mut stage_state,
+            mut score,
+            tick,
+            calibration,
+            spawner,
+            sound,
+        ): Self::SystemData,
     ) {
         if !stage_state.playing {
             return;
         }
-        let last_time = stage_state.time_in_song;
-        stage_state.time_in_song += time.delta_seconds();
-        let last_beat = last_time * ((stage_state.song.bpm as f32) / 60.0);
-        let new_beat = last_beat + (time.delta_seconds() * (stage_state.song.bpm as f32) / 60.0);
-        let last_sub_beat = (last_beat * SUBNOTES as f32) as i32;
-        let new_sub_beat = (new_beat * SUBNOTES as f32) as i32;
-        if new_sub_beat > last_sub_beat && new_sub_beat >= 0 {
-            for note in stage_state.song.get_notes_at(new_sub_beat) {
+        // Drops are scheduled late by the calibrated offset; their notes sound on the song clock.
+        let offset = calibration.offset;
+        let last_time = stage_state.time_in_song - offset;
+        stage_state.sync_to_clock(tick.clock, offset);
+        let now = stage_state.time_in_song - offset;
+        let last_sub_beat = stage_state.song.beat_at(last_time);
+        let new_sub_beat = stage_state.song.beat_at(now);
+        // A practice loop plays up to its end before going back to its start.
+        let play_until = stage_state
+            .loop_end()
+            .map_or(new_sub_beat, |end| new_sub_beat.min(end - 1));
+        // Every sub-beat crossed this frame, in order, so long frames don't skip notes.
+        for sub_beat in (last_sub_beat + 1).max(0)..=play_until {
+            if !stage_state.plays_beat(sub_beat) {
+                continue;
+            }
+            let elapsed = (now - stage_state.song.time_of_beat(sub_beat)).max(0.0);
+            if !stage_state.winning && !stage_state.losing {
+                if sub_beat == 0 {
+                    score.start_loop();
+                } else if sub_beat % stage_state.song.cycle_length().max(1) == 0 {
+                    score.end_loop();
+                }
+            }
+            for note in stage_state.song.get_notes_at(sub_beat) {
                 for (platform, entity) in (&platforms, &spawner.entities).join() {
                     if platform.note as usize == note {
+                        let mut ball = Ball::new(entity, offset, stage_state.lead_time);
+                        let fallen = ball.advance(elapsed);
+                        // Landed and cleared away before this frame, so judge it now. A
+                        // losing platform still needs the ball to die under it.
+                        if ball.ttl <= 0.0 && !stage_state.losing {
+                            if platform.has_player && !stage_state.winning {
+                                miss(&mut stage_state, &mut score, &sound);
+                            }
+                            continue;
+                        }
                         let mut ball_transform = Transform::default();
                         let mut shadow_transform = Transform::default();
                         if let Some(transform) = parents
@@ -568,7 +968,7 @@ impl<'s> System<'s> for PlatformBeatSystem {
                         {
                             ball_transform.set_translation_xyz(
                                 transform.translation().x,
-                                transform.translation().y + 100.0,
+                                transform.translation().y + DROP_HEIGHT - fallen,
                                 transform.translation().z + 0.01,
                             );
                             shadow_transform.set_translation_xyz(
@@ -577,13 +977,21 @@ impl<'s> System<'s> for PlatformBeatSystem {
                                 transform.translation().z + 0.01,
                             );
                         }
+                        let shadow = Shadow {
+                            ttl: stage_state.lead_time - elapsed,
+                        };
                         spawner.spawn_prefab(
                             |prefabs| &prefabs.shadow,
-                            move |builder| builder.with(shadow_transform).with(Shadow::default()),
+                            move |builder| builder.with(shadow_transform).with(shadow),
                         );
                         spawner.spawn_prefab(
                             |prefabs| &prefabs.ball,
-                            move |builder| builder.with(ball_transform).with(Ball::new(entity)),
+                            move |builder| {
+                                builder
+                                    .with(ball_transform)
+                                    .with(ball)
+                                    .with(Interpolated::default())
+                            },
                         );
                     }
                 }
@@ -596,7 +1004,7 @@ impl<'s> System<'s> for PlatformBeatSystem {
             if !stage_state.winning {
                 for note in stage_state
                     .song
-                    .get_rewards_at(new_sub_beat, &stage_state.notes_found)
+                    .get_rewards_at(sub_beat, &stage_state.notes_found)
                 {
                     for (platform, entity) in (&platforms, &spawner.entities).join() {
                         if platform.note as usize == note {
@@ -611,19 +1019,19 @@ impl<'s> System<'s> for PlatformBeatSystem {
                                     transform.translation().z + 0.01,
                                 );
                             }
+                            // Out for as long as it would have been since its sub-beat. One
+                            // already gone can still be picked up by a player standing on it.
+                            let pickup = NotePickup::new(entity, note, PICKUP_TIME - elapsed);
                             spawner.spawn_prefab(
                                 |prefabs| &prefabs.notes,
-                                move |builder| {
-                                    builder
-                                        .with(note_transform)
-                                        .with(NotePickup::new(entity, note, 5.))
-                                },
+                                move |builder| builder.with(note_transform).with(pickup),
                             );
                         }
                     }
                 }
             }
         }
+        stage_state.loop_practice(new_sub_beat);
     }
 }
 
@@ -643,6 +1051,7 @@ impl<'a, 'b> SystemBundle<'a, 'b> for StageBundle {
         dispatcher.add(PlayerMissSystem, "player_miss", &[]);
         dispatcher.add(PlayerNoteIndicatorSystem, "player_notes", &[]);
         dispatcher.add(PlayerWinSystem, "player_win", &[]);
+        dispatcher.add(ScoreHudSystem, "score_hud", &[]);
         Ok(())
     }
 }
@@ -654,24 +1063,51 @@ impl<'s> System<'s> for PlayerWinSystem {
         WriteStorage<'s, Platform>,
         WriteStorage<'s, Transform>,
         Write<'s, StageState>,
+        Write<'s, Score>,
+        Write<'s, Records>,
+        Write<'s, RunStats>,
+        Read<'s, Time>,
         SoundPlayer<'s>,
         PrefabSpawner<'s>,
     );
 
     fn run(
         &mut self,
-        (mut players, mut platforms, mut transforms, mut stage_state, sound, spawner): Self::SystemData,
+        (
+            mut players,
+            mut platforms,
+            mut transforms,
+            mut stage_state,
+            mut score,
+            mut records,
+            mut run,
+            time,
+            sound,
+            spawner,
+        ): Self::SystemData,
     ) {
-        if stage_state.notes_found.len() == 8 && !stage_state.winning {
+        if stage_state.playing && !stage_state.losing && stage_state.time_in_song > 0.0 {
+            run.time += time.fixed_seconds();
+        }
+        if stage_state.notes_found.len() == NOTES_TO_WIN && !stage_state.winning {
+            record_song(&mut records, &stage_state, &mut score, true);
+            run.end_song(stage_state.missed, true);
             stage_state.win();
-        } else if stage_state.missed >= 3 && !stage_state.losing {
+        } else if stage_state.missed >= stage_state.miss_limit()
+            && !stage_state.losing
+            && stage_state.practice().is_none()
+        {
+            record_song(&mut records, &stage_state, &mut score, false);
+            run.end_song(stage_state.missed, false);
+            run.lost_on = stage_state.song_name().to_string();
             stage_state.lose();
             for (player) in (&mut players).join() {
                 player.state = PlayerState::Dying { ttd: 0.3 };
             }
-        } else if stage_state.winning && stage_state.song.done(stage_state.beat()) {
+        } else if stage_state.winning && stage_state.song_finished() {
+            run.payouts += 1;
             stage_state.start_new_song();
-        } else if stage_state.losing && stage_state.song.done(stage_state.beat()) {
+        } else if stage_state.losing && stage_state.song_finished() {
             for (player) in (&mut players).join() {
                 match player.state {
                     PlayerState::Respawning { .. } => {}
@@ -683,20 +1119,23 @@ impl<'s> System<'s> for PlayerWinSystem {
                             }
                         }
                         if all_dead {
+                            run.finish(&score);
                             stage_state.reset();
+                            *score = Score::default();
                         }
                     }
                     _ => {}
                 }
             }
+        } else if stage_state.playing && !stage_state.losing && stage_state.song_finished() {
+            record_song(&mut records, &stage_state, &mut score, false);
+            run.end_song(stage_state.missed, false);
+            stage_state.next_song();
         } else if !stage_state.playing {
             for (player, transform) in (&mut players, &mut transforms).join() {
                 match player.state {
                     PlayerState::Waiting { .. } => {}
-                    _ => {
-                        println!("{:?}", player.state);
-                        stage_state.start_new_song();
-                    }
+                    _ => stage_state.start_new_song(),
                 }
             }
         } else if stage_state.losing || !stage_state.playing {
@@ -713,21 +1152,84 @@ impl<'s> System<'s> for PlayerWinSystem {
     }
 }
 
+// Practice runs don't count towards records.
+fn record_song(records: &mut Records, stage_state: &StageState, score: &mut Score, cleared: bool) {
+    if stage_state.practice().is_some() {
+        score.start_song();
+        return;
+    }
+    records.record(
+        stage_state.song_name(),
+        &SongResult {
+            score: score.song_points,
+            misses: stage_state.missed,
+            notes: stage_state.notes_found.len(),
+            best_combo: score.song_best_combo,
+            cleared,
+        },
+    );
+    if records.persistent {
+        if let Err(err) = records.save() {
+            error!("Failed to save records: {}", err);
+        }
+    }
+    score.start_song();
+}
+
 #[derive(Component, Debug, PrefabData, Clone, Deserialize, Serialize)]
 #[prefab(Component)]
 #[storage(VecStorage)]
 pub struct MissIndicator(i32);
 
+fn miss_pip_translation(pip: i32) -> (f32, f32) {
+    let index = pip - 1;
+    (
+        FIRST_MISS_PIP.0 + (index % MISS_PIPS_PER_ROW) as f32 * MISS_PIP_SPACING.0,
+        FIRST_MISS_PIP.1 + (index / MISS_PIPS_PER_ROW) as f32 * MISS_PIP_SPACING.1,
+    )
+}
+
 struct PlayerMissSystem;
 impl<'s> System<'s> for PlayerMissSystem {
     type SystemData = (
-        ReadStorage<'s, MissIndicator>,
+        Entities<'s>,
+        WriteStorage<'s, MissIndicator>,
         WriteStorage<'s, SpriteRender>,
+        WriteStorage<'s, Transform>,
         Write<'s, StageState>,
         SoundPlayer<'s>,
     );
 
-    fn run(&mut self, (misses, mut sprites, stage_state, sound): Self::SystemData) {
+    fn run(
+        &mut self,
+        (entities, mut misses, mut sprites, mut transforms, stage_state, sound): Self::SystemData,
+    ) {
+        // The backdrop comes with three pips. Copy one for every miss up to the
+        // limit and remove any past it.
+        let limit = stage_state.miss_limit();
+        let template = (&misses, &sprites, &transforms)
+            .join()
+            .next()
+            .map(|(_, sprite, transform)| (sprite.clone(), transform.clone()));
+        if let Some((sprite, transform)) = template {
+            let shown = (&misses).join().map(|miss| miss.0).collect::<Vec<i32>>();
+            for pip in (1..=limit).filter(|pip| !shown.contains(pip)) {
+                let (x, y) = miss_pip_translation(pip);
+                let mut transform = transform.clone();
+                transform.set_translation_x(x).set_translation_y(y);
+                entities
+                    .build_entity()
+                    .with(MissIndicator(pip), &mut misses)
+                    .with(sprite.clone(), &mut sprites)
+                    .with(transform, &mut transforms)
+                    .build();
+            }
+            for (entity, miss) in (&entities, &misses).join() {
+                if miss.0 > limit {
+                    entities.delete(entity).expect("Failed to remove miss pip");
+                }
+            }
+        }
         for (miss_num, mut sprite) in (&misses, &mut sprites).join() {
             if miss_num.0 <= stage_state.missed {
                 sprite.sprite_number = 5;
@@ -0,0 +1,106 @@
+use super::MenuState;
+use crate::assets::GameAssets;
+use crate::clock::{Calibration, SongClock};
+use crate::prelude::{self, *};
+use amethyst::{
+    input::{is_close_requested, is_key_down, InputEvent},
+    log::{error, info},
+    winit::VirtualKeyCode,
+};
+use imgui::im_str;
+
+const CLICK_INTERVAL: f64 = 0.6;
+// Clicks to settle into the rhythm before taps are measured.
+const WARMUP_CLICKS: usize = 4;
+const CALIBRATION_TAPS: usize = 8;
+
+/// Plays a steady click and measures how late the player taps along to it.
+pub struct CalibrationState {
+    assets: GameAssets,
+    start: f64,
+    clicks: usize,
+    deltas: Vec<f32>,
+}
+
+impl CalibrationState {
+    pub fn new(assets: GameAssets) -> Self {
+        CalibrationState {
+            assets,
+            start: 0.0,
+            clicks: 0,
+            deltas: Vec::new(),
+        }
+    }
+
+    fn finish(&self, world: &mut World) -> SimpleTrans {
+        if let Some(calibration) = Calibration::from_taps(self.deltas.clone()) {
+            info!("Calibrated audio offset: {:.3}s", calibration.offset);
+            if let Err(err) = calibration.save() {
+                error!("Failed to save calibration: {}", err);
+            }
+            world.insert(calibration);
+        }
+        SimpleTrans::Switch(Box::new(MenuState::new(
+            self.assets.clone(),
+            "main_menu.ron",
+        )))
+    }
+}
+
+impl SimpleState for CalibrationState {
+    fn on_start(&mut self, mut data: StateData<'_, GameData<'_, '_>>) {
+        data.world.delete_all();
+        data.world.insert(self.assets.2.clone());
+        self.start = data.world.read_resource::<SongClock>().now();
+    }
+
+    fn handle_event(
+        &mut self,
+        data: StateData<'_, GameData<'_, '_>>,
+        event: StateEvent,
+    ) -> SimpleTrans {
+        match &event {
+            StateEvent::Window(event) => {
+                if is_close_requested(&event) {
+                    Trans::Quit
+                } else if is_key_down(&event, VirtualKeyCode::Escape) {
+                    self.finish(data.world)
+                } else {
+                    Trans::None
+                }
+            }
+            StateEvent::Input(InputEvent::ActionPressed(action)) if action == "attack" => {
+                let elapsed = data.world.read_resource::<SongClock>().now() - self.start;
+                let nearest = (elapsed / CLICK_INTERVAL).round();
+                if nearest as usize >= WARMUP_CLICKS {
+                    self.deltas
+                        .push((elapsed - nearest * CLICK_INTERVAL) as f32);
+                }
+                if self.deltas.len() >= CALIBRATION_TAPS {
+                    self.finish(data.world)
+                } else {
+                    Trans::None
+                }
+            }
+            _ => Trans::None,
+        }
+    }
+
+    fn update(&mut self, data: &mut StateData<'_, GameData<'_, '_>>) -> SimpleTrans {
+        let elapsed = data.world.read_resource::<SongClock>().now() - self.start;
+        if elapsed >= self.clicks as f64 * CLICK_INTERVAL {
+            self.clicks += 1;
+            data.world
+                .exec(|sound: prelude::SoundPlayer| sound.play_normal(|store| &store.tap));
+        }
+        let taps = self.deltas.len();
+        amethyst_imgui::with(|ui: &imgui::Ui| {
+            imgui::Window::new(im_str!("Calibration")).build(ui, || {
+                ui.text(im_str!("Press space in time with the clicks."));
+                ui.text(im_str!("Taps: {} / {}", taps, CALIBRATION_TAPS));
+                ui.text(im_str!("Escape to skip."));
+            });
+        });
+        SimpleTrans::None
+    }
+}
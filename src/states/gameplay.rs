@@ -0,0 +1,137 @@
+use super::{PauseState, ResultsState};
+use crate::assets::GameAssets;
+use crate::autoplay::Autoplay;
+use crate::clock::{Calibration, SongClock};
+use crate::practice::Practice;
+use crate::prelude::{self, *};
+use crate::replay::ReplayMode;
+use crate::results::RunStats;
+use crate::settings::Settings;
+use crate::stage::*;
+use crate::tick::FixedStepper;
+use amethyst::{
+    input::{is_close_requested, is_key_down},
+    log::error,
+    ui::UiCreator,
+    window::ScreenDimensions,
+    winit::VirtualKeyCode,
+};
+
+pub struct GameplayState {
+    assets: GameAssets,
+    stage_desc: StageDescription,
+    // Name of the song to start on, or None for the first in the library.
+    song: Option<String>,
+    replay: ReplayMode,
+    stepper: Option<FixedStepper>,
+}
+
+impl GameplayState {
+    pub fn new(assets: GameAssets, stage_desc: StageDescription, song: Option<String>) -> Self {
+        GameplayState {
+            assets,
+            stage_desc,
+            song,
+            replay: ReplayMode::Off,
+            stepper: None,
+        }
+    }
+}
+
+impl SimpleState for GameplayState {
+    fn on_start(&mut self, mut data: StateData<'_, GameData<'_, '_>>) {
+        data.world.delete_all();
+        data.world.insert(self.assets.0.clone());
+        data.world.insert(self.assets.1.clone());
+        data.world.insert(self.assets.2.clone());
+        data.world.insert(self.assets.3.clone());
+
+        let offset = data.world.read_resource::<Calibration>().offset;
+        let args = std::env::args().collect::<Vec<String>>();
+        self.replay = {
+            let settings = data.world.read_resource::<Settings>();
+            ReplayMode::from_args(&args, offset, &settings)
+        };
+        data.world.insert(Autoplay {
+            enabled: args.iter().any(|arg| arg == "--autoplay"),
+        });
+
+        let dimensions = (*data.world.read_resource::<ScreenDimensions>()).clone();
+
+        initialize_camera(&mut data.world, &dimensions);
+        let mut songs = library_songs(data.world, &self.stage_desc);
+        if let Some(index) = self
+            .song
+            .as_ref()
+            .and_then(|name| songs.iter().position(|song| &song.name == name))
+        {
+            songs.rotate_left(index);
+        }
+        initialize_stage(&mut data.world, self.stage_desc.clone(), songs);
+        self.replay.start(data.world);
+        match Practice::from_args(&args) {
+            Ok(practice) => data
+                .world
+                .write_resource::<StageState>()
+                .set_practice(practice),
+            Err(err) => error!("{}", err),
+        }
+        self.stepper =
+            Some(FixedStepper::new(data.world).expect("Failed to build gameplay systems"));
+        data.world.exec(|mut creator: UiCreator<'_>| {
+            creator.create("hud.ron", ());
+        });
+    }
+
+    fn on_stop(&mut self, _data: StateData<'_, GameData<'_, '_>>) {
+        self.replay.finish();
+    }
+
+    // Nothing in the song moves while paused: gameplay runs on the audio clock
+    // and only animations run outside of it.
+    fn on_pause(&mut self, data: StateData<'_, GameData<'_, '_>>) {
+        data.world.write_resource::<SongClock>().pause();
+        prelude::pause_animations::<Transform>(data.world, true);
+        prelude::pause_animations::<SpriteRender>(data.world, true);
+    }
+
+    fn on_resume(&mut self, data: StateData<'_, GameData<'_, '_>>) {
+        data.world.write_resource::<SongClock>().resume();
+        prelude::pause_animations::<Transform>(data.world, false);
+        prelude::pause_animations::<SpriteRender>(data.world, false);
+    }
+
+    fn handle_event(
+        &mut self,
+        _data: StateData<'_, GameData<'_, '_>>,
+        event: StateEvent,
+    ) -> SimpleTrans {
+        match &event {
+            StateEvent::Window(event) if is_close_requested(&event) => Trans::Quit,
+            StateEvent::Window(event)
+                if is_key_down(&event, VirtualKeyCode::Escape)
+                    || is_key_down(&event, VirtualKeyCode::P) =>
+            {
+                Trans::Push(Box::new(PauseState::new(self.assets.clone())))
+            }
+            _ => Trans::None,
+        }
+    }
+
+    fn update(&mut self, data: &mut StateData<'_, GameData<'_, '_>>) -> SimpleTrans {
+        self.replay.begin_frame(data.world);
+        if let Some(stepper) = self.stepper.as_mut() {
+            stepper.step(data.world);
+        }
+        let results = data.world.write_resource::<RunStats>().take_results();
+        if let Some(results) = results {
+            return SimpleTrans::Switch(Box::new(ResultsState::new(
+                self.assets.clone(),
+                self.stage_desc.clone(),
+                self.song.clone(),
+                results,
+            )));
+        }
+        SimpleTrans::None
+    }
+}
@@ -0,0 +1,116 @@
+use super::{CalibrationState, GameplayState, MenuState};
+use crate::assets::*;
+use crate::clock::{Calibration, SongClock};
+use crate::prelude::*;
+use crate::records;
+use crate::settings::{self, Settings};
+use crate::stage::StageDescription;
+use amethyst::audio::output::init_output;
+use amethyst::log::error;
+
+#[derive(Default)]
+pub struct LoadingState {
+    progress: Option<ProgressCounter>,
+    assets: Option<GameAssets>,
+}
+
+impl SimpleState for LoadingState {
+    fn on_start(&mut self, mut data: StateData<'_, GameData<'_, '_>>) {
+        //        data.world.register::<PhysicsHandle>();
+        //        data.world.insert(AssetStorage::<TiledMap>::default());
+
+        println!("Starting loading");
+        init_output(data.world);
+        data.world.insert(Settings::load());
+        settings::apply_window(data.world);
+        let mut progress_counter = ProgressCounter::new();
+
+        let master = load_spritesheet(data.world, "Master".to_string(), &mut progress_counter);
+
+        let player = load_prefab(data.world, "Player.ron".to_string(), &mut progress_counter);
+        let notes = load_prefab(data.world, "Notes.ron".to_string(), &mut progress_counter);
+        let ball = load_prefab(
+            data.world,
+            "BallDrop.ron".to_string(),
+            &mut progress_counter,
+        );
+        let shadow = load_prefab(data.world, "Shadow.ron".to_string(), &mut progress_counter);
+        let platform = load_prefab(data.world, "Drops.ron".to_string(), &mut progress_counter);
+        let backdrop = load_prefab(
+            data.world,
+            "Backdrop.ron".to_string(),
+            &mut progress_counter,
+        );
+
+        let songs = load_song_library(data.world, &mut progress_counter);
+
+        let jump = load_sound_file(data.world, "hup.wav".to_string(), &mut progress_counter);
+        let tap = load_sound_file(data.world, "tap.wav".to_string(), &mut progress_counter);
+        let miss = load_sound_file(data.world, "tap.wav".to_string(), &mut progress_counter);
+
+        let instruments = load_instruments();
+        let note_scale =
+            load_instrument_scale(data.world, &instruments.note, &mut progress_counter);
+        let foo_scale = load_instrument_scale(data.world, &instruments.foo, &mut progress_counter);
+
+        self.progress = Some(progress_counter);
+        self.assets = Some((
+            SpriteStorage { master },
+            PrefabStorage {
+                ball,
+                shadow,
+                notes,
+                platform,
+                player,
+                backdrop,
+            },
+            SoundStorage {
+                jump,
+                tap,
+                miss,
+                foo_scale,
+                note_scale,
+                instruments,
+            },
+            songs,
+        ));
+    }
+
+    fn update(&mut self, data: &mut StateData<GameData>) -> SimpleTrans {
+        if let Some(progress) = &self.progress {
+            if progress.errors().len() > 0 {
+                error!("Failed to load assets: {:?}", progress.errors());
+                return SimpleTrans::Quit;
+            }
+            if progress.is_complete() {
+                let calibration = Calibration::load();
+                let recalibrate = std::env::args().any(|arg| arg == "--calibrate");
+                data.world.insert(SongClock::default());
+                data.world.insert(records::Records::load());
+                data.world.insert(calibration.clone().unwrap_or_default());
+                if calibration.is_none() || recalibrate {
+                    return SimpleTrans::Switch(Box::new(CalibrationState::new(
+                        self.assets.clone().unwrap(),
+                    )));
+                }
+                // Recorded sessions always start from the top of the library.
+                let args = std::env::args().collect::<Vec<String>>();
+                if args
+                    .iter()
+                    .any(|arg| arg == "--replay" || arg == "--record")
+                {
+                    return SimpleTrans::Switch(Box::new(GameplayState::new(
+                        self.assets.clone().unwrap(),
+                        StageDescription::default(),
+                        None,
+                    )));
+                }
+                return SimpleTrans::Switch(Box::new(MenuState::new(
+                    self.assets.clone().unwrap(),
+                    "main_menu.ron",
+                )));
+            }
+        }
+        SimpleTrans::None
+    }
+}
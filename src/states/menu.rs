@@ -0,0 +1,69 @@
+use super::{SettingsState, SongSelectState};
+use crate::assets::GameAssets;
+use crate::prelude::*;
+use amethyst::{
+    input::is_close_requested,
+    ui::{UiCreator, UiEventType, UiFinder},
+};
+
+pub struct MenuState {
+    assets: GameAssets,
+    menu: &'static str,
+}
+
+impl MenuState {
+    pub fn new(assets: GameAssets, menu: &'static str) -> Self {
+        MenuState { assets, menu }
+    }
+}
+
+impl SimpleState for MenuState {
+    fn on_start(&mut self, mut data: StateData<'_, GameData<'_, '_>>) {
+        data.world.delete_all();
+        data.world.exec(|mut creator: UiCreator<'_>| {
+            creator.create(format!("{}", self.menu), ());
+        });
+    }
+
+    fn handle_event(
+        &mut self,
+        data: StateData<'_, GameData<'_, '_>>,
+        event: StateEvent,
+    ) -> SimpleTrans {
+        match &event {
+            StateEvent::Window(event) => {
+                if is_close_requested(&event) {
+                    Trans::Quit
+                } else {
+                    Trans::None
+                }
+            }
+            StateEvent::Ui(ui_event) => data.world.exec(|finder: UiFinder<'_>| {
+                if ui_event.event_type == UiEventType::Click {
+                    if let Some(start) = finder.find("play") {
+                        if start == ui_event.target {
+                            return Trans::Switch(Box::new(SongSelectState::new(
+                                self.assets.clone(),
+                            )));
+                        }
+                    }
+                    if let Some(settings) = finder.find("settings") {
+                        if settings == ui_event.target {
+                            return Trans::Switch(Box::new(SettingsState::new(
+                                self.assets.clone(),
+                                false,
+                            )));
+                        }
+                    }
+                    if let Some(exit) = finder.find("exit") {
+                        if exit == ui_event.target {
+                            return Trans::Quit;
+                        }
+                    }
+                }
+                Trans::None
+            }),
+            _ => Trans::None,
+        }
+    }
+}
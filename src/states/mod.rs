@@ -0,0 +1,17 @@
+mod calibration;
+mod gameplay;
+mod loading;
+mod menu;
+mod pause;
+mod results;
+mod settings;
+mod song_select;
+
+pub use self::calibration::CalibrationState;
+pub use self::gameplay::GameplayState;
+pub use self::loading::LoadingState;
+pub use self::menu::MenuState;
+pub use self::pause::PauseState;
+pub use self::results::ResultsState;
+pub use self::settings::SettingsState;
+pub use self::song_select::SongSelectState;
@@ -0,0 +1,198 @@
+use super::{MenuState, SettingsState};
+use crate::assets::GameAssets;
+use crate::practice::{Practice, MAX_TEMPO};
+use crate::prelude::{self, *};
+use crate::stage::restart_song;
+use amethyst::{
+    input::{is_close_requested, is_key_down},
+    winit::VirtualKeyCode,
+};
+use imgui::im_str;
+
+// Beats counted in before the song carries on after a pause.
+const COUNTDOWN_BEATS: usize = 3;
+const TEMPO_STEP: f32 = 0.1;
+
+struct Countdown {
+    start: std::time::Instant,
+    beat_length: f32,
+    // Seconds from the start of the countdown until play resumes, so the next
+    // beat of the song falls one beat after the last click.
+    resume_at: f32,
+    clicks: usize,
+}
+
+/// Pushed over gameplay to pause it. Resuming counts in on the song's beat. The
+/// practice loop and tempo are set from here too.
+pub struct PauseState {
+    assets: GameAssets,
+    countdown: Option<Countdown>,
+}
+
+impl PauseState {
+    pub fn new(assets: GameAssets) -> Self {
+        PauseState {
+            assets,
+            countdown: None,
+        }
+    }
+
+    fn resume(&mut self, world: &World) -> SimpleTrans {
+        let stage_state = world.read_resource::<StageState>();
+        if !stage_state.playing || stage_state.song_finished() {
+            return Trans::Pop;
+        }
+        let (until_beat, beat_length) = stage_state.beat_timing();
+        self.countdown = Some(Countdown {
+            start: std::time::Instant::now(),
+            beat_length,
+            resume_at: beat_length * COUNTDOWN_BEATS as f32 - until_beat,
+            clicks: 0,
+        });
+        Trans::None
+    }
+
+    // Changes the practice loop, given the bar the song is in, and starts the song
+    // again to play it.
+    fn practice(&self, world: &mut World, change: impl FnOnce(Practice, &Song, i32) -> Practice) {
+        {
+            let mut stage_state = world.write_resource::<StageState>();
+            if !stage_state.playing || stage_state.winning || stage_state.losing {
+                return;
+            }
+            let practice = stage_state.practice().cloned().unwrap_or_default();
+            let (bar, _) = stage_state.song().bar_at(stage_state.beat().max(0));
+            let practice = change(practice, stage_state.song(), bar);
+            stage_state.set_practice(Some(practice));
+        }
+        restart_song(world);
+    }
+}
+
+impl SimpleState for PauseState {
+    fn handle_event(
+        &mut self,
+        data: StateData<'_, GameData<'_, '_>>,
+        event: StateEvent,
+    ) -> SimpleTrans {
+        let event = match &event {
+            StateEvent::Window(event) => event,
+            _ => return Trans::None,
+        };
+        if is_close_requested(&event) {
+            Trans::Quit
+        } else if self.countdown.is_some() {
+            Trans::None
+        } else if is_key_down(&event, VirtualKeyCode::Escape)
+            || is_key_down(&event, VirtualKeyCode::P)
+            || is_key_down(&event, VirtualKeyCode::Return)
+        {
+            self.resume(data.world)
+        } else if is_key_down(&event, VirtualKeyCode::R) {
+            restart_song(data.world);
+            Trans::Pop
+        } else if is_key_down(&event, VirtualKeyCode::LBracket) {
+            self.practice(data.world, |practice, song, bar| {
+                let start = song.bar_start(bar);
+                let end = practice.loop_end.filter(|end| *end > start);
+                Practice::new(start, end, practice.tempo)
+            });
+            Trans::None
+        } else if is_key_down(&event, VirtualKeyCode::RBracket) {
+            self.practice(data.world, |practice, song, bar| {
+                let end = song.bar_start(bar + 1);
+                Practice::new(practice.loop_start, Some(end), practice.tempo)
+            });
+            Trans::None
+        } else if is_key_down(&event, VirtualKeyCode::Minus) {
+            self.practice(data.world, |practice, _, _| {
+                Practice::new(
+                    practice.loop_start,
+                    practice.loop_end,
+                    practice.tempo - TEMPO_STEP,
+                )
+            });
+            Trans::None
+        } else if is_key_down(&event, VirtualKeyCode::Equals) {
+            self.practice(data.world, |practice, _, _| {
+                Practice::new(
+                    practice.loop_start,
+                    practice.loop_end,
+                    practice.tempo + TEMPO_STEP,
+                )
+            });
+            Trans::None
+        } else if is_key_down(&event, VirtualKeyCode::C) {
+            let practicing = data
+                .world
+                .read_resource::<StageState>()
+                .practice()
+                .is_some();
+            if practicing {
+                data.world.write_resource::<StageState>().set_practice(None);
+                restart_song(data.world);
+            }
+            Trans::None
+        } else if is_key_down(&event, VirtualKeyCode::S) {
+            Trans::Push(Box::new(SettingsState::new(self.assets.clone(), true)))
+        } else if is_key_down(&event, VirtualKeyCode::Q) {
+            Trans::Sequence(vec![
+                Trans::Pop,
+                Trans::Switch(Box::new(MenuState::new(
+                    self.assets.clone(),
+                    "main_menu.ron",
+                ))),
+            ])
+        } else {
+            Trans::None
+        }
+    }
+
+    fn update(&mut self, data: &mut StateData<'_, GameData<'_, '_>>) -> SimpleTrans {
+        if let Some(countdown) = self.countdown.as_mut() {
+            let elapsed = countdown.start.elapsed().as_secs_f32();
+            if countdown.clicks < COUNTDOWN_BEATS
+                && elapsed >= countdown.clicks as f32 * countdown.beat_length
+            {
+                countdown.clicks += 1;
+                data.world
+                    .exec(|sound: prelude::SoundPlayer| sound.play_normal(|store| &store.tap));
+            }
+            if elapsed >= countdown.resume_at {
+                return Trans::Pop;
+            }
+        }
+        let count = self
+            .countdown
+            .as_ref()
+            .map(|countdown| COUNTDOWN_BEATS + 1 - countdown.clicks);
+        let practice = match data.world.read_resource::<StageState>().practice() {
+            Some(practice) => format!(
+                "Practice: sub-beats {} to {} at {:.0}% tempo",
+                practice.loop_start,
+                practice
+                    .loop_end
+                    .map_or("the end".to_string(), |end| end.to_string()),
+                practice.tempo * 100.0
+            ),
+            None => format!("Practice: off, {:.0}% tempo", MAX_TEMPO * 100.0),
+        };
+        amethyst_imgui::with(|ui: &imgui::Ui| {
+            imgui::Window::new(im_str!("Paused")).build(ui, || match count {
+                Some(count) => ui.text(im_str!("{}", count)),
+                None => {
+                    ui.text(im_str!("Escape to resume"));
+                    ui.text(im_str!("R to restart the song"));
+                    ui.text(im_str!("S for settings"));
+                    ui.text(im_str!("Q to return to the menu"));
+                    ui.separator();
+                    ui.text(im_str!("{}", practice));
+                    ui.text(im_str!("[ and ] loop from this bar, or up to its end"));
+                    ui.text(im_str!("- and = change the tempo"));
+                    ui.text(im_str!("C to stop practicing"));
+                }
+            });
+        });
+        Trans::None
+    }
+}
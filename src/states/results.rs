@@ -0,0 +1,113 @@
+use super::{GameplayState, SongSelectState};
+use crate::assets::GameAssets;
+use crate::prelude::*;
+use crate::results::RunResults;
+use crate::stage::StageDescription;
+use amethyst::{
+    input::{is_close_requested, is_key_down},
+    ui::{UiCreator, UiEventType, UiFinder},
+    winit::VirtualKeyCode,
+};
+use imgui::im_str;
+
+/// Shows how a run went once it is lost, with options to go again or pick
+/// another song.
+pub struct ResultsState {
+    assets: GameAssets,
+    stage_desc: StageDescription,
+    song: Option<String>,
+    results: RunResults,
+}
+
+impl ResultsState {
+    pub fn new(
+        assets: GameAssets,
+        stage_desc: StageDescription,
+        song: Option<String>,
+        results: RunResults,
+    ) -> Self {
+        ResultsState {
+            assets,
+            stage_desc,
+            song,
+            results,
+        }
+    }
+
+    fn retry(&self) -> SimpleTrans {
+        Trans::Switch(Box::new(GameplayState::new(
+            self.assets.clone(),
+            self.stage_desc.clone(),
+            self.song.clone(),
+        )))
+    }
+
+    fn menu(&self) -> SimpleTrans {
+        Trans::Switch(Box::new(SongSelectState::new(self.assets.clone())))
+    }
+}
+
+impl SimpleState for ResultsState {
+    fn on_start(&mut self, mut data: StateData<'_, GameData<'_, '_>>) {
+        data.world.delete_all();
+        data.world.exec(|mut creator: UiCreator<'_>| {
+            creator.create("game_over.ron", ());
+        });
+    }
+
+    fn handle_event(
+        &mut self,
+        data: StateData<'_, GameData<'_, '_>>,
+        event: StateEvent,
+    ) -> SimpleTrans {
+        match &event {
+            StateEvent::Window(event) => {
+                if is_close_requested(&event) {
+                    Trans::Quit
+                } else if is_key_down(&event, VirtualKeyCode::Return)
+                    || is_key_down(&event, VirtualKeyCode::R)
+                {
+                    self.retry()
+                } else if is_key_down(&event, VirtualKeyCode::Escape) {
+                    self.menu()
+                } else {
+                    Trans::None
+                }
+            }
+            StateEvent::Ui(ui_event) if ui_event.event_type == UiEventType::Click => {
+                let (play, menu) = data
+                    .world
+                    .exec(|finder: UiFinder<'_>| (finder.find("play"), finder.find("menu")));
+                if play == Some(ui_event.target) {
+                    self.retry()
+                } else if menu == Some(ui_event.target) {
+                    self.menu()
+                } else {
+                    Trans::None
+                }
+            }
+            _ => Trans::None,
+        }
+    }
+
+    fn update(&mut self, _data: &mut StateData<'_, GameData<'_, '_>>) -> SimpleTrans {
+        let results = &self.results;
+        amethyst_imgui::with(|ui: &imgui::Ui| {
+            imgui::Window::new(im_str!("Game over")).build(ui, || {
+                ui.text(im_str!("Lost on {}", results.song));
+                ui.text(im_str!(
+                    "Survived {}:{:02}",
+                    results.time as i32 / 60,
+                    results.time as i32 % 60
+                ));
+                ui.text(im_str!("Misses: {}", results.misses));
+                ui.text(im_str!("Notes collected: {}", results.notes));
+                ui.text(im_str!("Songs cleared: {}", results.songs_cleared));
+                ui.text(im_str!("Payout songs completed: {}", results.payouts));
+                ui.text(im_str!("Best combo: {}", results.best_combo));
+                ui.text(im_str!("Score: {}", results.score));
+            });
+        });
+        Trans::None
+    }
+}
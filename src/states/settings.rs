@@ -0,0 +1,173 @@
+use super::MenuState;
+use crate::assets::GameAssets;
+use crate::prelude::*;
+use crate::settings::{self, Settings};
+use amethyst::{
+    input::{is_close_requested, is_key_down},
+    log::error,
+    winit::VirtualKeyCode,
+};
+use imgui::im_str;
+
+const SETTINGS_ROWS: usize = 7;
+const VOLUME_STEP: f32 = 0.1;
+const LEAD_TIME_STEP: f32 = 0.1;
+
+/// Audio, video and gameplay options. Changes apply as they are made and are
+/// saved on leaving.
+pub struct SettingsState {
+    assets: GameAssets,
+    // Whether this was pushed over a paused game rather than opened from the menu.
+    paused: bool,
+    selected: usize,
+}
+
+impl SettingsState {
+    pub fn new(assets: GameAssets, paused: bool) -> Self {
+        SettingsState {
+            assets,
+            paused,
+            selected: 0,
+        }
+    }
+
+    // Moves the selected option one step up or down.
+    fn adjust(&self, world: &mut World, step: i32) {
+        {
+            let mut settings = world.write_resource::<Settings>();
+            let volume = |volume: f32| volume + VOLUME_STEP * step as f32;
+            let adjusted = match self.selected {
+                0 => Settings {
+                    master_volume: volume(settings.master_volume),
+                    ..settings.clone()
+                },
+                1 => Settings {
+                    music_volume: volume(settings.music_volume),
+                    ..settings.clone()
+                },
+                2 => Settings {
+                    sfx_volume: volume(settings.sfx_volume),
+                    ..settings.clone()
+                },
+                3 => {
+                    let sizes = settings::WINDOW_SIZES.len() as i32;
+                    let current = settings::WINDOW_SIZES
+                        .iter()
+                        .position(|size| *size == settings.window_size)
+                        .unwrap_or(0) as i32;
+                    Settings {
+                        window_size: settings::WINDOW_SIZES
+                            [((current + step + sizes) % sizes) as usize],
+                        ..settings.clone()
+                    }
+                }
+                4 => Settings {
+                    fullscreen: !settings.fullscreen,
+                    ..settings.clone()
+                },
+                5 => Settings {
+                    miss_limit: settings.miss_limit + step,
+                    ..settings.clone()
+                },
+                _ => Settings {
+                    lead_time: settings.lead_time + LEAD_TIME_STEP * step as f32,
+                    ..settings.clone()
+                },
+            };
+            *settings = adjusted.clamped();
+        }
+        if self.selected == 3 || self.selected == 4 {
+            settings::apply_window(world);
+        }
+    }
+
+    fn leave(&self, world: &World) -> SimpleTrans {
+        if let Err(err) = world.read_resource::<Settings>().save() {
+            error!("Failed to save settings: {}", err);
+        }
+        if self.paused {
+            Trans::Pop
+        } else {
+            Trans::Switch(Box::new(MenuState::new(
+                self.assets.clone(),
+                "main_menu.ron",
+            )))
+        }
+    }
+}
+
+impl SimpleState for SettingsState {
+    fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
+        if !self.paused {
+            data.world.delete_all();
+        }
+    }
+
+    fn handle_event(
+        &mut self,
+        data: StateData<'_, GameData<'_, '_>>,
+        event: StateEvent,
+    ) -> SimpleTrans {
+        let event = match &event {
+            StateEvent::Window(event) => event,
+            _ => return Trans::None,
+        };
+        if is_close_requested(&event) {
+            return Trans::Quit;
+        } else if is_key_down(&event, VirtualKeyCode::Escape)
+            || is_key_down(&event, VirtualKeyCode::Return)
+        {
+            return self.leave(data.world);
+        } else if is_key_down(&event, VirtualKeyCode::Up) || is_key_down(&event, VirtualKeyCode::W)
+        {
+            self.selected = (self.selected + SETTINGS_ROWS - 1) % SETTINGS_ROWS;
+        } else if is_key_down(&event, VirtualKeyCode::Down)
+            || is_key_down(&event, VirtualKeyCode::S)
+        {
+            self.selected = (self.selected + 1) % SETTINGS_ROWS;
+        } else if is_key_down(&event, VirtualKeyCode::Left)
+            || is_key_down(&event, VirtualKeyCode::A)
+        {
+            self.adjust(data.world, -1);
+        } else if is_key_down(&event, VirtualKeyCode::Right)
+            || is_key_down(&event, VirtualKeyCode::D)
+        {
+            self.adjust(data.world, 1);
+        }
+        Trans::None
+    }
+
+    fn update(&mut self, data: &mut StateData<'_, GameData<'_, '_>>) -> SimpleTrans {
+        let settings = data.world.read_resource::<Settings>().clone();
+        let percent = |volume: f32| (volume * 100.0).round() as i32;
+        let rows = [
+            format!("Master volume: {}%", percent(settings.master_volume)),
+            format!("Music volume: {}%", percent(settings.music_volume)),
+            format!("Effects volume: {}%", percent(settings.sfx_volume)),
+            format!(
+                "Window size: {}x{}",
+                settings.window_size.0, settings.window_size.1
+            ),
+            format!(
+                "Fullscreen: {}",
+                if settings.fullscreen { "on" } else { "off" }
+            ),
+            format!("Misses to lose a song: {}", settings.miss_limit),
+            format!("Ball lead time: {:.1}s", settings.lead_time),
+        ];
+        let selected = self.selected;
+        amethyst_imgui::with(|ui: &imgui::Ui| {
+            imgui::Window::new(im_str!("Settings")).build(ui, || {
+                for (index, row) in rows.iter().enumerate() {
+                    let marker = if index == selected { ">" } else { " " };
+                    ui.text(im_str!("{} {}", marker, row));
+                }
+                ui.separator();
+                ui.text(im_str!("Up/down to pick, left/right to change."));
+                ui.text(im_str!("Lead time applies from the next game."));
+                ui.text(im_str!("Escape to save and go back."));
+            });
+        });
+        Trans::None
+    }
+}
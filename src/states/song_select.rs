@@ -0,0 +1,191 @@
+use super::{GameplayState, MenuState};
+use crate::assets::GameAssets;
+use crate::player::JUMP_SPEED;
+use crate::prelude::*;
+use crate::settings::Settings;
+use crate::stage::{StageDescription, BALL_DROP_TIME};
+use crate::{analysis, records, validate};
+use amethyst::{
+    assets::AssetStorage,
+    input::{is_close_requested, is_key_down},
+    winit::VirtualKeyCode,
+};
+use imgui::im_str;
+
+// One song in the song list, as it plays on the chosen layout.
+struct SongEntry {
+    song: Song,
+    playable: bool,
+    rating: f32,
+    grade: &'static str,
+    best: Option<records::SongRecord>,
+}
+
+/// Lists the song library with each song's details and the player's bests, and
+/// starts the chosen song on the chosen stage layout.
+pub struct SongSelectState {
+    assets: GameAssets,
+    songs: Vec<Song>,
+    entries: Vec<SongEntry>,
+    selected: usize,
+    layout: usize,
+}
+
+impl SongSelectState {
+    pub fn new(assets: GameAssets) -> Self {
+        SongSelectState {
+            assets,
+            songs: Vec::new(),
+            entries: Vec::new(),
+            selected: 0,
+            layout: 0,
+        }
+    }
+
+    fn refresh(&mut self, world: &World) {
+        let (_, stage) = &StageDescription::layouts()[self.layout];
+        let records = world.read_resource::<records::Records>();
+        // Rated with the lead time the song will be played with.
+        let lead_time = world
+            .try_fetch::<Settings>()
+            .map_or(BALL_DROP_TIME, |settings| settings.lead_time);
+        self.entries = self
+            .songs
+            .iter()
+            .map(|song| {
+                let analysis = analysis::analyze_song(song, stage, JUMP_SPEED, lead_time);
+                SongEntry {
+                    song: song.clone(),
+                    playable: !validate::has_errors(&validate::validate_song(song, stage)),
+                    rating: analysis.rating,
+                    grade: analysis.grade(),
+                    best: records.song(&song.name).cloned(),
+                }
+            })
+            .collect();
+    }
+
+    fn play(&self) -> SimpleTrans {
+        match self.entries.get(self.selected) {
+            Some(entry) if entry.playable => SimpleTrans::Switch(Box::new(GameplayState::new(
+                self.assets.clone(),
+                StageDescription::layouts()[self.layout].1.clone(),
+                Some(entry.song.name.clone()),
+            ))),
+            _ => SimpleTrans::None,
+        }
+    }
+}
+
+impl SimpleState for SongSelectState {
+    fn on_start(&mut self, mut data: StateData<'_, GameData<'_, '_>>) {
+        data.world.delete_all();
+        self.songs = {
+            let library = data.world.read_resource::<SongStorage>();
+            let song_assets = data.world.read_resource::<AssetStorage<Song>>();
+            library
+                .songs
+                .iter()
+                .filter_map(|handle| song_assets.get(handle).cloned())
+                .collect()
+        };
+        self.refresh(data.world);
+    }
+
+    fn handle_event(
+        &mut self,
+        data: StateData<'_, GameData<'_, '_>>,
+        event: StateEvent,
+    ) -> SimpleTrans {
+        let event = match &event {
+            StateEvent::Window(event) => event,
+            _ => return Trans::None,
+        };
+        let songs = self.entries.len().max(1);
+        let layouts = StageDescription::layouts().len();
+        if is_close_requested(&event) {
+            return Trans::Quit;
+        } else if is_key_down(&event, VirtualKeyCode::Escape) {
+            return Trans::Switch(Box::new(MenuState::new(
+                self.assets.clone(),
+                "main_menu.ron",
+            )));
+        } else if is_key_down(&event, VirtualKeyCode::Return)
+            || is_key_down(&event, VirtualKeyCode::Space)
+        {
+            return self.play();
+        } else if is_key_down(&event, VirtualKeyCode::Up) || is_key_down(&event, VirtualKeyCode::W)
+        {
+            self.selected = (self.selected + songs - 1) % songs;
+        } else if is_key_down(&event, VirtualKeyCode::Down)
+            || is_key_down(&event, VirtualKeyCode::S)
+        {
+            self.selected = (self.selected + 1) % songs;
+        } else if is_key_down(&event, VirtualKeyCode::Left)
+            || is_key_down(&event, VirtualKeyCode::A)
+        {
+            self.layout = (self.layout + layouts - 1) % layouts;
+            self.refresh(data.world);
+        } else if is_key_down(&event, VirtualKeyCode::Right)
+            || is_key_down(&event, VirtualKeyCode::D)
+        {
+            self.layout = (self.layout + 1) % layouts;
+            self.refresh(data.world);
+        }
+        Trans::None
+    }
+
+    fn update(&mut self, _data: &mut StateData<'_, GameData<'_, '_>>) -> SimpleTrans {
+        let layouts = StageDescription::layouts();
+        let (layout_name, layout) = &layouts[self.layout];
+        let (width, height) = layout.size();
+        let entries = &self.entries;
+        let selected = self.selected;
+        amethyst_imgui::with(|ui: &imgui::Ui| {
+            imgui::Window::new(im_str!("Songs")).build(ui, || {
+                ui.text(im_str!("Stage: < {} {}x{} >", layout_name, width, height));
+                ui.separator();
+                for (index, entry) in entries.iter().enumerate() {
+                    let song = &entry.song;
+                    let marker = if index == selected { ">" } else { " " };
+                    let length = match song.duration() {
+                        Some(duration) => format!("{:.0}s", duration),
+                        None => "loops".to_string(),
+                    };
+                    ui.text(im_str!(
+                        "{} {} by {}, {} bpm, {}, {:.1} {}",
+                        marker,
+                        song.name,
+                        song.author,
+                        song.bpm,
+                        length,
+                        entry.rating,
+                        entry.grade
+                    ));
+                    if !entry.playable {
+                        ui.text(im_str!("    not playable on this stage"));
+                    } else if let Some(best) = &entry.best {
+                        let misses = best
+                            .fewest_misses
+                            .map_or("-".to_string(), |misses| misses.to_string());
+                        ui.text(im_str!(
+                            "    best {} points, {} notes, {} clears in {} plays, fewest misses {}",
+                            best.best_score,
+                            best.best_notes,
+                            best.clears,
+                            best.plays,
+                            misses
+                        ));
+                    } else {
+                        ui.text(im_str!("    not played yet"));
+                    }
+                }
+                ui.separator();
+                ui.text(im_str!(
+                    "Up/down to pick a song, left/right to pick a stage, enter to play."
+                ));
+            });
+        });
+        SimpleTrans::None
+    }
+}
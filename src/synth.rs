@@ -0,0 +1,154 @@
+use crate::prelude::*;
+use std::f32::consts::PI;
+use std::io::{Cursor, Seek, Write};
+
+pub const SYNTH_RATE: u32 = 44100;
+// Frequency of C4, note 0 in SCALE.
+const C4_FREQUENCY: f32 = 261.63;
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+pub enum Waveform {
+    Sine,
+    Square,
+    Triangle,
+    Saw,
+}
+
+impl Waveform {
+    fn at(&self, phase: f32) -> f32 {
+        match self {
+            Waveform::Sine => f32::sin(phase * 2.0 * PI),
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Triangle => 1.0 - 4.0 * f32::abs(phase - 0.5),
+            Waveform::Saw => 2.0 * phase - 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize, Serialize)]
+pub struct Envelope {
+    pub attack: f32,
+    pub decay: f32,
+    pub sustain: f32,
+    pub release: f32,
+}
+
+impl Envelope {
+    fn at(&self, time: f32, held: f32) -> f32 {
+        let level = if time < self.attack {
+            time / self.attack
+        } else if time < self.attack + self.decay {
+            lerp((time - self.attack) / self.decay, 1.0, self.sustain)
+        } else {
+            self.sustain
+        };
+        if time < held {
+            level
+        } else if self.release > 0.0 {
+            level * f32::max(1.0 - (time - held) / self.release, 0.0)
+        } else {
+            0.0
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SynthPatch {
+    pub waveform: Waveform,
+    pub envelope: Envelope,
+    /// Seconds the note is held before it is released.
+    pub length: f32,
+    pub volume: f32,
+}
+
+impl SynthPatch {
+    pub fn bell() -> Self {
+        SynthPatch {
+            waveform: Waveform::Sine,
+            envelope: Envelope {
+                attack: 0.005,
+                decay: 0.3,
+                sustain: 0.3,
+                release: 0.4,
+            },
+            length: 0.2,
+            volume: 0.8,
+        }
+    }
+
+    pub fn chip() -> Self {
+        SynthPatch {
+            waveform: Waveform::Square,
+            envelope: Envelope {
+                attack: 0.005,
+                decay: 0.05,
+                sustain: 0.6,
+                release: 0.05,
+            },
+            length: 0.15,
+            volume: 0.3,
+        }
+    }
+
+    pub fn pluck() -> Self {
+        SynthPatch {
+            waveform: Waveform::Triangle,
+            envelope: Envelope {
+                attack: 0.002,
+                decay: 0.15,
+                sustain: 0.0,
+                release: 0.0,
+            },
+            length: 0.15,
+            volume: 0.7,
+        }
+    }
+
+    pub fn synthesize(&self, note: Note, rate: u32) -> Vec<f32> {
+        let frequency = note_frequency(note);
+        let frames = ((self.length + self.envelope.release) * rate as f32) as usize;
+        (0..frames)
+            .map(|frame| {
+                let time = frame as f32 / rate as f32;
+                let phase = (time * frequency).fract();
+                self.waveform.at(phase) * self.envelope.at(time, self.length) * self.volume
+            })
+            .collect()
+    }
+}
+
+pub fn note_frequency(note: Note) -> f32 {
+    C4_FREQUENCY * f32::powf(2.0, note as f32 / 12.0)
+}
+
+pub fn write_samples<W: Write + Seek>(
+    out: W,
+    buffer: &[f32],
+    rate: u32,
+) -> Result<(), hound::Error> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::new(out, spec)?;
+    for sample in buffer.iter() {
+        writer.write_sample((sample.max(-1.0).min(1.0) * std::i16::MAX as f32) as i16)?;
+    }
+    writer.finalize()
+}
+
+/// A synthesized note as WAV file bytes, ready to load as a `Source`.
+pub fn synthesize_wav(patch: &SynthPatch, note: Note) -> Vec<u8> {
+    let mut cursor = Cursor::new(Vec::new());
+    write_samples(&mut cursor, &patch.synthesize(note, SYNTH_RATE), SYNTH_RATE)
+        .expect("Failed to encode synthesized note");
+    cursor.into_inner()
+}
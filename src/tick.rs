@@ -0,0 +1,158 @@
+use crate::clock::SongClock;
+use crate::hazards::HazardsBundle;
+use crate::player::PlayerBundle;
+use crate::prelude::*;
+use crate::stage::StageBundle;
+use amethyst::core::math::Vector3;
+
+/// Length of one gameplay tick. Gameplay systems integrate with
+/// `time.fixed_seconds()`, which is set to this.
+pub const TICK_SECONDS: f32 = 1.0 / 120.0;
+// Longest stretch of time run in one frame. Anything more after a hitch is
+// skipped, and the SongClock is moved back by it so the song waits for the balls.
+const MAX_CATCH_UP: f64 = 0.25;
+
+/// Timing of the gameplay tick being run.
+#[derive(Debug, Clone, Default)]
+pub struct FixedTick {
+    /// SongClock time the current tick simulates up to.
+    pub clock: f64,
+    /// How far rendering is between the last two ticks.
+    pub alpha: f32,
+    pub ticks: u64,
+    last_clock: Option<f64>,
+    accumulator: f64,
+}
+
+impl FixedTick {
+    // Number of ticks due by `now`, and the seconds skipped past MAX_CATCH_UP.
+    fn advance(&mut self, now: f64) -> (u32, f64) {
+        let elapsed = match self.last_clock {
+            Some(last) => (now - last).max(0.0),
+            None => {
+                self.clock = now;
+                0.0
+            }
+        };
+        let due = self.accumulator + elapsed;
+        let skipped = (due - MAX_CATCH_UP).max(0.0);
+        self.last_clock = Some(now - skipped);
+        self.accumulator = due - skipped;
+        let ticks = (self.accumulator / TICK_SECONDS as f64).floor();
+        self.accumulator -= ticks * TICK_SECONDS as f64;
+        self.alpha = (self.accumulator / TICK_SECONDS as f64) as f32;
+        (ticks as u32, skipped)
+    }
+
+    fn step(&mut self) {
+        self.clock += TICK_SECONDS as f64;
+        self.ticks += 1;
+    }
+}
+
+/// Renders an entity between its positions at the last two ticks.
+#[derive(Component, Debug, Clone, Default)]
+#[storage(VecStorage)]
+pub struct Interpolated {
+    previous: Option<Vector3<f32>>,
+    current: Option<Vector3<f32>>,
+}
+
+/// Runs the stage, player and hazard systems on a fixed tick, however long frames are.
+pub struct FixedStepper {
+    dispatcher: Dispatcher<'static, 'static>,
+}
+
+impl FixedStepper {
+    pub fn new(world: &mut World) -> Result<Self, Error> {
+        let mut builder = DispatcherBuilder::new();
+        StageBundle.build(world, &mut builder)?;
+        PlayerBundle.build(world, &mut builder)?;
+        HazardsBundle.build(world, &mut builder)?;
+        let mut dispatcher = builder.build();
+        dispatcher.setup(world);
+        world.register::<Interpolated>();
+        world.insert(FixedTick::default());
+        world
+            .write_resource::<Time>()
+            .set_fixed_seconds(TICK_SECONDS);
+        Ok(FixedStepper { dispatcher })
+    }
+
+    /// Runs every tick due since the last frame, then places interpolated entities.
+    pub fn step(&mut self, world: &mut World) {
+        let now = world.read_resource::<SongClock>().now();
+        let (ticks, skipped) = world.write_resource::<FixedTick>().advance(now);
+        if skipped > 0.0 {
+            world.write_resource::<SongClock>().skip(skipped);
+        }
+        if ticks > 0 {
+            world.exec(
+                |(mut interpolated, mut transforms): (
+                    WriteStorage<Interpolated>,
+                    WriteStorage<Transform>,
+                )| {
+                    for (interpolated, transform) in (&mut interpolated, &mut transforms).join() {
+                        if let Some(current) = interpolated.current {
+                            transform.set_translation(current);
+                        }
+                    }
+                },
+            );
+        }
+        for _ in 0..ticks {
+            world.exec(
+                |(mut interpolated, transforms): (
+                    WriteStorage<Interpolated>,
+                    ReadStorage<Transform>,
+                )| {
+                    for (interpolated, transform) in (&mut interpolated, &transforms).join() {
+                        interpolated.previous = Some(*transform.translation());
+                    }
+                },
+            );
+            world.write_resource::<FixedTick>().step();
+            self.dispatcher.dispatch(world);
+            world.maintain();
+        }
+        let alpha = world.read_resource::<FixedTick>().alpha;
+        world.exec(
+            |(mut interpolated, mut transforms): (
+                WriteStorage<Interpolated>,
+                WriteStorage<Transform>,
+            )| {
+                for (interpolated, transform) in (&mut interpolated, &mut transforms).join() {
+                    let current = match interpolated.current {
+                        Some(current) if ticks == 0 => current,
+                        _ => *transform.translation(),
+                    };
+                    interpolated.current = Some(current);
+                    if let Some(previous) = interpolated.previous {
+                        transform.set_translation(previous.lerp(&current, alpha));
+                    }
+                }
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hitches_only_move_the_clock_by_the_ticks_run() {
+        let mut tick = FixedTick::default();
+        assert_eq!(tick.advance(10.0), (0, 0.0));
+        let (ticks, skipped) = tick.advance(11.0);
+        assert_eq!(ticks, (MAX_CATCH_UP / TICK_SECONDS as f64).floor() as u32);
+        assert!((skipped - (1.0 - MAX_CATCH_UP)).abs() < 1e-9);
+        for _ in 0..ticks {
+            tick.step();
+        }
+        assert!((tick.clock + tick.accumulator - (11.0 - skipped)).abs() < 1e-9);
+        // The next frame carries on from the moved clock.
+        let (ticks, skipped) = tick.advance(11.0 - skipped + TICK_SECONDS as f64);
+        assert_eq!((ticks, skipped), (1, 0.0));
+    }
+}
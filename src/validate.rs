@@ -0,0 +1,578 @@
+use crate::assets::SCALE;
+use crate::music::{ostinato_bar_length, Substructure};
+use crate::prelude::*;
+use crate::stage::{StageDescription, NOTES_TO_WIN};
+use amethyst::log::{error, warn};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub location: String,
+    pub beat: Option<i32>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        match self.beat {
+            Some(beat) => write!(
+                f,
+                "{}: {}, beat {}: {}",
+                severity, self.location, beat, self.message
+            ),
+            None => write!(f, "{}: {}: {}", severity, self.location, self.message),
+        }
+    }
+}
+
+struct Diagnostics(Vec<Diagnostic>);
+
+impl Diagnostics {
+    fn push(&mut self, severity: Severity, location: &str, beat: Option<i32>, message: String) {
+        self.0.push(Diagnostic {
+            severity,
+            location: location.to_string(),
+            beat,
+            message,
+        });
+    }
+}
+
+fn note_name(note: Note) -> String {
+    SCALE
+        .get(note)
+        .map(|name| name.to_string())
+        .unwrap_or_else(|| format!("note {}", note))
+}
+
+// The field a structure divides sub-beats by, which must be positive.
+fn divisor(structure: &Substructure, subdivision: i32) -> (&'static str, i32) {
+    match structure {
+        Substructure::Round { restart_at, .. } => ("restart_at", *restart_at),
+        Substructure::Scale { interval, .. } => ("interval", *interval),
+        Substructure::Chord { restart_at, .. } => ("restart_at", *restart_at),
+        Substructure::Arpeggio { rate, .. } => ("rate", *rate),
+        Substructure::Ostinato { bar_length, .. } => {
+            ("bar_length", ostinato_bar_length(*bar_length, subdivision))
+        }
+    }
+}
+
+fn check_divisor(
+    diagnostics: &mut Diagnostics,
+    location: &str,
+    structure: &Substructure,
+    subdivision: i32,
+) -> bool {
+    let (field, value) = divisor(structure, subdivision);
+    if value <= 0 {
+        diagnostics.push(
+            Severity::Error,
+            location,
+            None,
+            format!("{} is {}, it must be positive", field, value),
+        );
+    }
+    value > 0
+}
+
+fn check_structure(
+    diagnostics: &mut Diagnostics,
+    location: &str,
+    structure: &Substructure,
+    subdivision: i32,
+) -> bool {
+    if !check_divisor(diagnostics, location, structure, subdivision) {
+        return false;
+    }
+    match structure {
+        Substructure::Round {
+            notes,
+            rounds,
+            repeat_at,
+            restart_at,
+            pitch_up,
+            loops,
+        } => {
+            if loops.map_or(false, |loops| loops <= 0) {
+                diagnostics.push(
+                    Severity::Error,
+                    location,
+                    None,
+                    format!("loops is {}, the structure never plays", loops.unwrap()),
+                );
+            }
+            if *rounds <= 0 {
+                diagnostics.push(
+                    Severity::Error,
+                    location,
+                    None,
+                    format!("rounds is {}, no voice will ever play", rounds),
+                );
+            }
+            let mut beats = notes.keys().cloned().collect::<Vec<i32>>();
+            beats.sort();
+            for beat in beats {
+                let note = notes[&beat];
+                if note >= NOTE_COUNT {
+                    diagnostics.push(
+                        Severity::Error,
+                        location,
+                        Some(beat),
+                        format!(
+                            "note {} is outside the {} note scale and wraps to {}",
+                            note,
+                            NOTE_COUNT,
+                            note_name(note % NOTE_COUNT)
+                        ),
+                    );
+                }
+                if beat < 0 || beat >= *restart_at {
+                    diagnostics.push(
+                        Severity::Error,
+                        location,
+                        Some(beat),
+                        format!(
+                            "beat is never reached, the round restarts at {}",
+                            restart_at
+                        ),
+                    );
+                    continue;
+                }
+                for voice in 1..*rounds {
+                    let voice_beat = beat + repeat_at * voice;
+                    if voice_beat >= *restart_at {
+                        diagnostics.push(
+                            Severity::Warning,
+                            location,
+                            Some(beat),
+                            format!(
+                                "voice {} would play this at {}, after the restart at {}",
+                                voice + 1,
+                                voice_beat,
+                                restart_at
+                            ),
+                        );
+                    } else if note < NOTE_COUNT && note + pitch_up * voice as usize >= NOTE_COUNT {
+                        diagnostics.push(
+                            Severity::Warning,
+                            location,
+                            Some(beat),
+                            format!(
+                                "voice {} pitches {} up to {}, which wraps to {}",
+                                voice + 1,
+                                note_name(note),
+                                note + pitch_up * voice as usize,
+                                note_name((note + pitch_up * voice as usize) % NOTE_COUNT)
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+        Substructure::Scale { notes, interval } => {
+            for (index, note) in notes.iter().enumerate() {
+                if *note >= NOTE_COUNT {
+                    diagnostics.push(
+                        Severity::Error,
+                        location,
+                        Some(index as i32 * interval),
+                        format!("note {} has no platform", note),
+                    );
+                }
+            }
+        }
+        Substructure::Chord {
+            notes,
+            restart_at,
+            loops,
+        } => {
+            if loops.map_or(false, |loops| loops <= 0) {
+                diagnostics.push(
+                    Severity::Error,
+                    location,
+                    None,
+                    format!("loops is {}, the chords never play", loops.unwrap()),
+                );
+            }
+            let mut beats = notes.keys().cloned().collect::<Vec<i32>>();
+            beats.sort();
+            for beat in beats {
+                if beat < 0 || beat >= *restart_at {
+                    diagnostics.push(
+                        Severity::Error,
+                        location,
+                        Some(beat),
+                        format!("chord is never reached, chords restart at {}", restart_at),
+                    );
+                }
+                check_notes(diagnostics, location, Some(beat), &notes[&beat]);
+            }
+        }
+        Substructure::Arpeggio { notes, loops, .. } => {
+            if *loops <= 0 || notes.is_empty() {
+                diagnostics.push(
+                    Severity::Warning,
+                    location,
+                    None,
+                    "arpeggio never plays a note".to_string(),
+                );
+            }
+            check_notes(diagnostics, location, None, notes);
+        }
+        Substructure::Ostinato {
+            notes,
+            bar_length,
+            bars,
+            ..
+        } => {
+            if *bars <= 0 {
+                diagnostics.push(
+                    Severity::Warning,
+                    location,
+                    None,
+                    format!("bars is {}, the figure never plays", bars),
+                );
+            }
+            let bar_length = ostinato_bar_length(*bar_length, subdivision);
+            let mut beats = notes.keys().cloned().collect::<Vec<i32>>();
+            beats.sort();
+            for beat in beats {
+                if beat < 0 || beat >= bar_length {
+                    diagnostics.push(
+                        Severity::Error,
+                        location,
+                        Some(beat),
+                        format!("beat is outside the {} beat bar", bar_length),
+                    );
+                }
+                check_notes(diagnostics, location, Some(beat), &vec![notes[&beat]]);
+            }
+        }
+    }
+    true
+}
+
+fn check_notes(
+    diagnostics: &mut Diagnostics,
+    location: &str,
+    beat: Option<i32>,
+    notes: &Vec<Note>,
+) {
+    for note in notes.iter() {
+        if *note >= NOTE_COUNT {
+            diagnostics.push(
+                Severity::Error,
+                location,
+                beat,
+                format!("note {} has no platform", note),
+            );
+        }
+    }
+}
+
+fn check_drops(
+    diagnostics: &mut Diagnostics,
+    song: &Song,
+    structures: &[(String, &Substructure)],
+    stage: &StageDescription,
+) {
+    let (width, height) = stage.size();
+    let length = song.length().unwrap_or_else(|| song.cycle_length());
+    for beat in 0..length {
+        let mut drops: HashMap<Note, Vec<String>> = HashMap::new();
+        for (location, structure) in structures.iter() {
+            for (voice, note) in structure.get_voices_at(beat, song.subdivision) {
+                drops.entry(note).or_insert_with(Vec::new).push(format!(
+                    "{} voice {}",
+                    location,
+                    voice + 1
+                ));
+            }
+        }
+        let mut notes = drops.keys().cloned().collect::<Vec<Note>>();
+        notes.sort();
+        for note in notes.iter() {
+            let voices = &drops[note];
+            if voices.len() > 1 {
+                diagnostics.push(
+                    Severity::Warning,
+                    &song.name,
+                    Some(beat),
+                    format!(
+                        "{} overlap on {} ({})",
+                        voices.len(),
+                        note_name(*note),
+                        voices.join(", ")
+                    ),
+                );
+            }
+            if *note < NOTE_COUNT && !stage.has_platform(*note) {
+                diagnostics.push(
+                    Severity::Error,
+                    &song.name,
+                    Some(beat),
+                    format!(
+                        "{} has no platform on a {}x{} stage",
+                        note_name(*note),
+                        width,
+                        height
+                    ),
+                );
+            }
+            if stage.has_platform(*note)
+                && stage
+                    .neighbours(*note)
+                    .iter()
+                    .all(|neighbour| drops.contains_key(neighbour))
+            {
+                diagnostics.push(
+                    Severity::Warning,
+                    &song.name,
+                    Some(beat),
+                    format!(
+                        "{} and all of its neighbours are hit at once, it cannot be dodged",
+                        note_name(*note)
+                    ),
+                );
+            }
+        }
+    }
+}
+
+fn check_payouts(diagnostics: &mut Diagnostics, song: &Song, stage: &StageDescription) {
+    let length = song.length().unwrap_or_else(|| song.cycle_length());
+    let mut reachable = Vec::new();
+    for (index, payout) in song.payouts.iter().enumerate() {
+        let location = format!("{} payout {}", song.name, index);
+        if let Substructure::Round { notes, .. } = payout {
+            for (beat, note) in notes.iter() {
+                if *beat >= length {
+                    diagnostics.push(
+                        Severity::Warning,
+                        &location,
+                        Some(*beat),
+                        format!(
+                            "{} is paid out after the song ends at {}",
+                            note_name(*note),
+                            length
+                        ),
+                    );
+                }
+            }
+        }
+        for beat in 0..length {
+            for note in payout.get_notes_at(beat, song.subdivision) {
+                if stage.has_platform(note) && !reachable.contains(&note) {
+                    reachable.push(note);
+                }
+            }
+        }
+    }
+    if reachable.len() < NOTES_TO_WIN {
+        diagnostics.push(
+            Severity::Error,
+            &song.name,
+            None,
+            format!(
+                "only {} different notes are paid out, {} are needed to win",
+                reachable.len(),
+                NOTES_TO_WIN
+            ),
+        );
+    }
+    for note in song.next_notes.iter() {
+        if *note >= NOTE_COUNT {
+            diagnostics.push(
+                Severity::Error,
+                &song.name,
+                None,
+                format!("next note {} has no platform", note),
+            );
+        }
+    }
+}
+
+fn check_timing(diagnostics: &mut Diagnostics, song: &Song) {
+    if song.bpm <= 0 {
+        diagnostics.push(
+            Severity::Error,
+            &song.name,
+            None,
+            format!("bpm is {}, it must be positive", song.bpm),
+        );
+    }
+    if song.subdivision <= 0 {
+        diagnostics.push(
+            Severity::Error,
+            &song.name,
+            None,
+            format!("subdivision is {}, it must be positive", song.subdivision),
+        );
+    }
+}
+
+/// Checks only what would stop a song being played at all, such as dividing
+/// by zero, for tools that play charts the game wouldn't accept yet.
+pub fn validate_timing(song: &Song) -> Vec<Diagnostic> {
+    let mut diagnostics = Diagnostics(Vec::new());
+    check_timing(&mut diagnostics, song);
+    for (index, structure) in song.structures.iter().enumerate() {
+        let location = format!("{} structure {}", song.name, index);
+        check_divisor(&mut diagnostics, &location, structure, song.subdivision);
+    }
+    for (index, payout) in song.payouts.iter().enumerate() {
+        let location = format!("{} payout {}", song.name, index);
+        check_divisor(&mut diagnostics, &location, payout, song.subdivision);
+    }
+    diagnostics.0
+}
+
+pub fn validate_song(song: &Song, stage: &StageDescription) -> Vec<Diagnostic> {
+    let mut diagnostics = Diagnostics(Vec::new());
+    check_timing(&mut diagnostics, song);
+    if song.swing < 0.0 || song.swing >= 1.0 {
+        diagnostics.push(
+            Severity::Error,
+            &song.name,
+            None,
+            format!("swing is {}, it must be at least 0 and below 1", song.swing),
+        );
+    }
+    for change in song.tempo_map.iter() {
+        if change.bpm <= 0.0 || change.beat <= 0 {
+            diagnostics.push(
+                Severity::Warning,
+                &song.name,
+                Some(change.beat),
+                format!(
+                    "tempo change to {} bpm is ignored, it needs a positive beat and bpm",
+                    change.bpm
+                ),
+            );
+        }
+    }
+    for meter in song.meters.iter() {
+        if meter.beats_per_bar <= 0 || meter.beat_unit <= 0 || meter.beat < 0 {
+            diagnostics.push(
+                Severity::Error,
+                &song.name,
+                Some(meter.beat),
+                format!(
+                    "{}/{} is not a valid time signature",
+                    meter.beats_per_bar, meter.beat_unit
+                ),
+            );
+        }
+    }
+    if song.structures.is_empty() {
+        diagnostics.push(
+            Severity::Error,
+            &song.name,
+            None,
+            "song has no structures".to_string(),
+        );
+    }
+    let mut playable = true;
+    let mut structures = Vec::new();
+    for (index, structure) in song.structures.iter().enumerate() {
+        let location = format!("{} structure {}", song.name, index);
+        playable &= check_structure(&mut diagnostics, &location, structure, song.subdivision);
+        structures.push((location, structure));
+    }
+    for (index, payout) in song.payouts.iter().enumerate() {
+        let location = format!("{} payout {}", song.name, index);
+        playable &= check_structure(&mut diagnostics, &location, payout, song.subdivision);
+    }
+    if playable {
+        check_drops(&mut diagnostics, song, &structures, stage);
+        check_payouts(&mut diagnostics, song, stage);
+    }
+    diagnostics.0
+}
+
+/// Logs diagnostics at their severity.
+pub fn log_diagnostics(diagnostics: &[Diagnostic]) {
+    for diagnostic in diagnostics.iter() {
+        match diagnostic.severity {
+            Severity::Warning => warn!("{}", diagnostic),
+            Severity::Error => error!("{}", diagnostic),
+        }
+    }
+}
+
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.severity == Severity::Error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scale(interval: i32) -> Song {
+        Song {
+            name: "Scale".to_string(),
+            bpm: 60,
+            structures: vec![Substructure::Scale {
+                notes: vec![0, 1, 2],
+                interval,
+            }],
+            ..Song::default()
+        }
+    }
+
+    #[test]
+    fn songs_without_payouts_can_still_be_played() {
+        let song = scale(4);
+        assert!(has_errors(&validate_song(
+            &song,
+            &StageDescription::default()
+        )));
+        assert!(validate_timing(&song).is_empty());
+    }
+
+    #[test]
+    fn zero_divisors_cannot_be_played() {
+        assert!(has_errors(&validate_timing(&scale(0))));
+        assert!(has_errors(&validate_timing(&Song { bpm: 0, ..scale(4) })));
+    }
+
+    #[test]
+    fn ostinato_bars_default_to_four_beats_of_the_song() {
+        let mut notes = HashMap::new();
+        notes.insert(11, 0);
+        let song = Song {
+            structures: vec![Substructure::Ostinato {
+                notes,
+                start: 0,
+                bar_length: None,
+                bars: 2,
+            }],
+            subdivision: 3,
+            ..scale(4)
+        };
+        assert_eq!(song.cycle_length(), 24);
+        let mut diagnostics = Diagnostics(Vec::new());
+        assert!(check_structure(
+            &mut diagnostics,
+            "ostinato",
+            &song.structures[0],
+            song.subdivision
+        ));
+        assert!(!has_errors(&diagnostics.0));
+    }
+}